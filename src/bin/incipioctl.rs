@@ -0,0 +1,101 @@
+// The test harness needs std and provides its own `main`
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+
+/// The protocol spoken over incipio's control socket
+// Decoding requests is only done by incipio itself
+#[allow(dead_code)]
+#[path = "../control/protocol.rs"]
+mod protocol;
+
+/// Sends requests through incipio's control socket
+#[path = "../control/client.rs"]
+mod client;
+
+use core::ffi::{c_char, CStr};
+
+use libc_print::{libc_eprintln, libc_print};
+use nix::libc::{EXIT_FAILURE, EXIT_SUCCESS};
+
+use self::{
+    client::send_request,
+    protocol::{Command, Request, Status, MAX_RESPONSE_SIZE},
+};
+
+/// Exit status used for invalid command lines, as opposed to
+/// requests that incipio refused
+const EXIT_USAGE: isize = 2;
+
+fn print_usage() {
+    libc_eprintln!(
+        "Usage: incipioctl <command> [service|target]
+
+Commands:
+    status            list services and their states
+    start <service>   start a service
+    stop <service>    stop a service
+    restart <service> restart a service
+    poweroff          shut down and power off the machine
+    reboot            shut down and reboot the machine
+    halt              shut down and halt the machine
+    rescue            stop everything and run a shell on the console
+    target <target>   switch to rescue, multi-user or graphical"
+    );
+}
+
+/// Returns the `idx`-th argument, if there's one
+fn argument<'a>(
+    argc: isize,
+    argv: *const *const c_char,
+    idx: isize,
+) -> Option<&'a CStr> {
+    // Safety: the C runtime hands us `argc` valid strings
+    (idx < argc).then(|| unsafe { CStr::from_ptr(*argv.offset(idx)) })
+}
+
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn main(
+    argc: isize,
+    argv: *const *const c_char,
+) -> isize {
+    let Some(command) = argument(argc, argv, 1)
+        .and_then(|name| Command::from_name(name.to_bytes()))
+    else {
+        print_usage();
+        return EXIT_USAGE;
+    };
+
+    let argument =
+        argument(argc, argv, 2).map_or(&[][..], CStr::to_bytes);
+    if command.takes_argument() == argument.is_empty() || argc > 3 {
+        print_usage();
+        return EXIT_USAGE;
+    }
+
+    let request = Request { command, argument };
+    let mut response = [0; MAX_RESPONSE_SIZE];
+
+    match send_request(&request, &mut response) {
+        Ok((status, message)) => {
+            let message = core::str::from_utf8(message).unwrap_or("");
+
+            match status {
+                Status::Ok => {
+                    libc_print!("{}", message);
+                    EXIT_SUCCESS as isize
+                }
+                Status::Error => {
+                    libc_eprintln!("incipioctl: {}", message);
+                    EXIT_FAILURE as isize
+                }
+            }
+        }
+        Err(errno) => {
+            libc_eprintln!(
+                "incipioctl: failed to reach incipio: {}",
+                errno
+            );
+            EXIT_FAILURE as isize
+        }
+    }
+}
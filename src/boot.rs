@@ -1,35 +1,73 @@
+use core::time::Duration;
+
 use libc_print::libc_eprintln;
 use nix::{
+    errno::Errno,
     fcntl::{open, OFlag},
     libc::{reboot, LINUX_REBOOT_CMD_CAD_OFF},
-    sys::stat::Mode,
-    unistd::{close, write},
+    sys::{
+        signal::{kill, Signal},
+        stat::Mode,
+    },
+    unistd::{close, pause, sync, write},
 };
 
 use crate::{
+    cmdline::BootOptions,
+    config::Config,
+    fixed::FixedCStr,
     mount::{turn_off_swap_partitions, unmount_all_filesystems},
-    rand_seed::SEED,
+    pid::for_each_process,
+    power::PowerAction,
+    seed::{load_random_seed, save_random_seed},
+    supervisor::Supervisor,
     tty::open_ttys,
-    utils::FileMapping,
+    utils::{monotonic_time, sleep_for, FileMapping},
+    wait::reap_child_processes,
 };
 
-pub fn boot_up_system() -> crate::Result<()> {
+/// How long SIGKILLed processes have to go away, which only takes
+/// longer than an instant for processes stuck in the kernel
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often we check whether processes have exited
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn boot_up_system(
+    supervisor: &mut Supervisor,
+    config: &Config,
+    boot_options: &BootOptions,
+) {
     // Set hostname by reading /etc/hostname
-    set_hostname()?;
+    if let Err(err) = set_hostname() {
+        libc_eprintln!(
+            "Failed to set the hostname: {}",
+            err.description()
+        );
+    }
 
-    // Seed /dev/urandom
-    seed_urandom()?;
+    // Seed the kernel's entropy pool from the previous boot
+    load_random_seed();
 
     // Stop CAD from rebooting the system
     disable_control_alt_del();
 
-    // Open TTYs
-    open_ttys();
-
-    Ok(())
+    // Open TTYs, which get respawned whenever they exit
+    let only = boot_options.ttys.as_ref().map(FixedCStr::as_bytes);
+    open_ttys(supervisor, &config.ttys, only);
 }
 
-pub fn boot_down_system() -> crate::Result<()> {
+/// Brings the system down to the point where it's safe to reboot:
+/// every process is stopped, swap is turned off and filesystems
+/// are unmounted.
+pub fn boot_down_system(config: &Config) -> crate::Result<()> {
+    // Processes with open files would keep their filesystems from
+    // being unmounted
+    kill_all_processes(config.kill_timeout);
+
+    // Must happen while /var is still mounted
+    save_random_seed();
+
     if let Err(err) = turn_off_swap_partitions() {
         libc_eprintln!(
             "Failed to turn off swap partitions: {}",
@@ -37,23 +75,107 @@ pub fn boot_down_system() -> crate::Result<()> {
         );
     }
 
-    // nftw()
-    // nix::libc::nftw
-
     unmount_all_filesystems()
 }
 
-/// Seed `/dev/urandom`
-fn seed_urandom() -> crate::Result<()> {
-    let raw_fd = nix::fcntl::open(
-        "/dev/urandom",
-        OFlag::O_WRONLY,
-        Mode::S_IRWXU,
-    )?;
-    write(raw_fd, SEED)?;
-    close(raw_fd)?;
+/// Stops every process, unmounts all filesystems and then asks the
+/// kernel to reboot, power off or halt the machine, according to
+/// `action`.
+///
+/// Only returns control if `reboot(2)` itself fails, in which case
+/// we hang around forever since PID 1 must never exit.
+pub fn shutdown_system(action: PowerAction, config: &Config) -> ! {
+    if let Err(err) = boot_down_system(config) {
+        libc_eprintln!(
+            "Failed to boot down system: {}",
+            err.description()
+        );
+    }
 
-    Ok(())
+    // Flush whatever is left of the filesystem buffers
+    sync();
+
+    let ret_val = unsafe { reboot(action.reboot_command()) };
+    if let Err(errno) = Errno::result(ret_val) {
+        libc_eprintln!("reboot(2) failed: {}", errno);
+    }
+
+    loop {
+        pause();
+    }
+}
+
+/// Asks every process to terminate, giving them `timeout` to do so
+/// before they get SIGKILLed.
+pub fn kill_all_processes(timeout: Duration) {
+    crate::info!("Sending SIGTERM to all processes");
+    signal_all_processes(Signal::SIGTERM);
+    // Stopped processes only act on SIGTERM once they're resumed
+    signal_all_processes(Signal::SIGCONT);
+
+    if wait_for_processes_to_exit(timeout) {
+        return;
+    }
+
+    crate::info!("Sending SIGKILL to all processes");
+    signal_all_processes(Signal::SIGKILL);
+
+    if !wait_for_processes_to_exit(KILL_TIMEOUT) {
+        libc_eprintln!("Some processes are still running");
+    }
+}
+
+/// Sends `signal` to every process but incipio and kernel threads
+fn signal_all_processes(signal: Signal) {
+    let result = for_each_process(|pid| {
+        // ESRCH just means that the process exited in the meantime
+        match kill(pid, signal) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(errno) => {
+                libc_eprintln!(
+                    "Failed to send {} to {}: {}",
+                    signal,
+                    pid,
+                    errno
+                )
+            }
+        }
+    });
+
+    if let Err(err) = result {
+        libc_eprintln!(
+            "Failed to list processes: {}",
+            err.description()
+        );
+    }
+}
+
+/// Reaps exiting processes until none is left or until `timeout`
+/// expires. Returns whether every process exited.
+fn wait_for_processes_to_exit(timeout: Duration) -> bool {
+    let deadline = monotonic_time() + timeout;
+
+    loop {
+        // Orphans are reparented to us, so most processes exiting
+        // end up as our zombies
+        reap_child_processes(drop);
+
+        let mut remaining = 0;
+        if for_each_process(|_| remaining += 1).is_err() {
+            // Without /proc there's no telling, so use up the timeout
+            remaining = 1;
+        }
+
+        if remaining == 0 {
+            return true;
+        }
+
+        if monotonic_time() >= deadline {
+            return false;
+        }
+
+        sleep_for(POLL_INTERVAL);
+    }
 }
 
 fn set_hostname() -> crate::Result<()> {
@@ -81,7 +203,7 @@ fn set_hostname() -> crate::Result<()> {
 /// After this call, CAD will only send a SIGINT interrupt to PID 1.
 fn disable_control_alt_del() {
     // After CAD is disabled, the CAD keystroke will cause a SIGINT
-    // signal to be sent to incipio causing it to reap children
-    // processes.
+    // signal to be sent to incipio, which reboots the system in both
+    // the incipio and busybox profiles.
     unsafe { reboot(LINUX_REBOOT_CMD_CAD_OFF) };
 }
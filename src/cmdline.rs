@@ -0,0 +1,292 @@
+use cstr::cstr;
+use libc_print::libc_eprintln;
+
+use crate::{fixed::FixedCStr, log::LogLevel, utils::read_file};
+
+/// The most bytes of the kernel command line we look at, which is
+/// more than what most architectures allow
+const MAX_CMDLINE_SIZE: usize = 4096;
+
+/// The command line the kernel was booted with, as read from
+/// `/proc/cmdline`.
+///
+/// It's read into a fixed buffer rather than through a
+/// `FileMapping`, since files in `/proc` report a size of 0 and so
+/// can't be mapped.
+pub struct KernelCommandLine {
+    buffer: [u8; MAX_CMDLINE_SIZE],
+    len: usize,
+}
+
+impl KernelCommandLine {
+    /// Reads `/proc/cmdline`, which must already be mounted
+    pub fn read() -> crate::Result<Self> {
+        let mut cmdline = Self {
+            buffer: [0; MAX_CMDLINE_SIZE],
+            len: 0,
+        };
+        cmdline.len =
+            read_file(cstr!("/proc/cmdline"), &mut cmdline.buffer)?;
+
+        Ok(cmdline)
+    }
+
+    /// Iterates over the parameters, in order
+    pub fn parameters(&self) -> Parameters<'_> {
+        Parameters {
+            rest: &self.buffer[..self.len],
+        }
+    }
+
+    /// The value of the `key=value` parameter called `key`. When
+    /// given more than once, the last one wins, like it does for the
+    /// kernel.
+    pub fn value(&self, key: &[u8]) -> Option<&[u8]> {
+        self.parameters()
+            .filter(|parameter| parameter.key == key)
+            .filter_map(|parameter| parameter.value)
+            .last()
+    }
+
+    /// Whether `flag` was given, with or without a value
+    pub fn has_flag(&self, flag: &[u8]) -> bool {
+        self.parameters().any(|parameter| parameter.key == flag)
+    }
+}
+
+/// A parameter of the kernel command line: either a flag such as
+/// `quiet` or a `key=value` pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parameter<'a> {
+    pub key: &'a [u8],
+    pub value: Option<&'a [u8]>,
+}
+
+/// Iterates over the whitespace-separated parameters of the kernel
+/// command line.
+///
+/// Like the kernel, whitespace inside double quotes does not split
+/// parameters, so both `"key=some value"` and `key="some value"`
+/// have `some value` as their value.
+pub struct Parameters<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Parameters<'a> {
+    type Item = Parameter<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_ascii_start();
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let mut in_quotes = false;
+        let end = self
+            .rest
+            .iter()
+            .position(|&byte| {
+                if byte == b'"' {
+                    in_quotes = !in_quotes;
+                }
+                byte.is_ascii_whitespace() && !in_quotes
+            })
+            .unwrap_or(self.rest.len());
+
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+
+        let word = strip_quotes(word);
+        let parameter =
+            match word.iter().position(|&byte| byte == b'=') {
+                Some(idx) => Parameter {
+                    key: &word[..idx],
+                    value: Some(strip_quotes(&word[idx + 1..])),
+                },
+                None => Parameter {
+                    key: word,
+                    value: None,
+                },
+            };
+
+        Some(parameter)
+    }
+}
+
+fn strip_quotes(word: &[u8]) -> &[u8] {
+    let word = word.strip_prefix(b"\"").unwrap_or(word);
+    word.strip_suffix(b"\"").unwrap_or(word)
+}
+
+/// How far the boot goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// Everything is started
+    Normal,
+    /// `single`: a root shell is run on the console once root is
+    /// remounted, before the rest of `/etc/fstab`, gettys and
+    /// services
+    Single,
+    /// `emergency`: a root shell is run on the console as soon as
+    /// possible, before even mounting filesystems
+    Emergency,
+}
+
+/// The parts of the kernel command line incipio cares about
+pub struct BootOptions {
+    pub mode: BootMode,
+    /// `ro` or `rw`, for when root has no entry in `/etc/fstab`
+    pub root_read_only: Option<bool>,
+    /// `quiet` or `incipio.debug`
+    pub log_level: LogLevel,
+    /// `incipio.ttys=`, a comma-separated list of TTYs to run
+    /// gettys on instead of the usual ones
+    pub ttys: Option<FixedCStr<128>>,
+}
+
+impl BootOptions {
+    pub const fn new() -> Self {
+        Self {
+            mode: BootMode::Normal,
+            root_read_only: None,
+            log_level: LogLevel::Normal,
+            ttys: None,
+        }
+    }
+
+    /// Reads the boot options from `/proc/cmdline`, falling back to
+    /// the defaults if that's not possible.
+    pub fn read() -> Self {
+        match KernelCommandLine::read() {
+            Ok(cmdline) => Self::from_cmdline(&cmdline),
+            Err(err) => {
+                libc_eprintln!(
+                    "Failed to read the kernel command line: {}",
+                    err.description()
+                );
+                Self::new()
+            }
+        }
+    }
+
+    pub fn from_cmdline(cmdline: &KernelCommandLine) -> Self {
+        let mut options = Self::new();
+
+        for parameter in cmdline.parameters() {
+            match (parameter.key, parameter.value) {
+                // sysvinit also takes `S`, `s` and `1` for runlevel S
+                (b"single" | b"S" | b"s" | b"1", None) => {
+                    options.mode = BootMode::Single
+                }
+                (b"emergency" | b"-b", None) => {
+                    options.mode = BootMode::Emergency
+                }
+                (b"ro", None) => options.root_read_only = Some(true),
+                (b"rw", None) => options.root_read_only = Some(false),
+                (b"quiet", None) => {
+                    options.log_level = LogLevel::Quiet
+                }
+                (b"incipio.debug", None) => {
+                    options.log_level = LogLevel::Debug
+                }
+                (b"incipio.ttys", Some(ttys)) => {
+                    match FixedCStr::from_bytes(ttys) {
+                        Ok(ttys) => options.ttys = Some(ttys),
+                        Err(err) => {
+                            libc_eprintln!(
+                                "Ignoring incipio.ttys: {}",
+                                err.description()
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        options
+    }
+}
+
+impl Default for BootOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(line: &[u8]) -> KernelCommandLine {
+        let mut cmdline = KernelCommandLine {
+            buffer: [0; MAX_CMDLINE_SIZE],
+            len: line.len(),
+        };
+        cmdline.buffer[..line.len()].copy_from_slice(line);
+        cmdline
+    }
+
+    #[test]
+    fn splits_flags_and_values() {
+        let cmdline = cmdline(b"  quiet root=/dev/sda1\n");
+        let mut parameters = cmdline.parameters();
+
+        assert_eq!(
+            parameters.next(),
+            Some(Parameter {
+                key: b"quiet",
+                value: None
+            })
+        );
+        assert_eq!(
+            parameters.next(),
+            Some(Parameter {
+                key: b"root",
+                value: Some(b"/dev/sda1")
+            })
+        );
+        assert_eq!(parameters.next(), None);
+    }
+
+    #[test]
+    fn keeps_quoted_whitespace() {
+        let cmdline = cmdline(br#"a="b c" "d=e f" g"#);
+
+        assert_eq!(cmdline.value(b"a"), Some(&b"b c"[..]));
+        assert_eq!(cmdline.value(b"d"), Some(&b"e f"[..]));
+        assert!(cmdline.has_flag(b"g"));
+    }
+
+    #[test]
+    fn last_value_wins() {
+        let cmdline = cmdline(b"root=/dev/sda1 root=/dev/sdb1");
+
+        assert_eq!(cmdline.value(b"root"), Some(&b"/dev/sdb1"[..]));
+    }
+
+    #[test]
+    fn reads_boot_options() {
+        let options = BootOptions::from_cmdline(&cmdline(
+            b"ro single incipio.debug incipio.ttys=tty1,ttyS0",
+        ));
+
+        assert_eq!(options.mode, BootMode::Single);
+        assert_eq!(options.root_read_only, Some(true));
+        assert_eq!(options.log_level, LogLevel::Debug);
+        assert_eq!(
+            options.ttys.as_ref().map(FixedCStr::as_bytes),
+            Some(&b"tty1,ttyS0"[..])
+        );
+    }
+
+    #[test]
+    fn defaults_without_parameters() {
+        let options = BootOptions::from_cmdline(&cmdline(b""));
+
+        assert_eq!(options.mode, BootMode::Normal);
+        assert_eq!(options.root_read_only, None);
+        assert_eq!(options.log_level, LogLevel::Normal);
+        assert!(options.ttys.is_none());
+    }
+}
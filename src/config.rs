@@ -0,0 +1,166 @@
+use core::{ffi::CStr, time::Duration};
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::errno::Errno;
+
+use crate::{
+    fixed::FixedCStr,
+    parser::{parse_number, KeyValues},
+    signal::SignalProfile,
+    target::Target,
+    tty::{Tty, Ttys},
+    utils::FileMapping,
+    Error,
+};
+
+/// incipio's own configuration file, which is entirely optional.
+///
+/// It looks like:
+///
+/// ```text
+/// # Which signals power off, reboot and halt the system: `incipio`
+/// # (the default) or `busybox`. See `SignalProfile`.
+/// signals = busybox
+/// # How many seconds processes get to exit on shutdown after being
+/// # sent SIGTERM, before they're SIGKILLed. Defaults to 5.
+/// kill_timeout = 10
+/// # What runs on the console when booting fails. Defaults to
+/// # /bin/sh, which asks for no password.
+/// emergency_shell = /sbin/sulogin
+/// # The target booted into: `rescue`, `multi-user` or `graphical`
+/// # (the default). See `Target`.
+/// default_target = multi-user
+/// # A TTY to run a getty on. May be repeated. When given at all,
+/// # tty1 to tty8 are no longer used. See `Tty::parse`.
+/// tty = tty1
+/// tty = ttyS0 baud=115200 term=vt220 -- --autologin root
+/// ```
+///
+/// Serial lines the kernel uses as consoles always get a getty.
+pub static CONFIG_PATH: &CStr = cstr!("/etc/incipio/incipio.conf");
+
+/// The emergency shell unless configured otherwise
+static DEFAULT_SHELL: &CStr = cstr!("/bin/sh");
+
+/// The settings read from [`CONFIG_PATH`]
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub signal_profile: SignalProfile,
+    /// How long processes have to exit after SIGTERM on shutdown
+    pub kill_timeout: Duration,
+    /// The program run on the console when booting fails, if not
+    /// [`DEFAULT_SHELL`]
+    pub emergency_shell: Option<ShellPath>,
+    /// The target booted into
+    pub default_target: Target,
+    /// The TTYs gettys run on
+    pub ttys: Ttys,
+}
+
+/// The path of the emergency shell
+pub type ShellPath = FixedCStr<128>;
+
+impl Config {
+    /// The configuration used when [`CONFIG_PATH`] doesn't exist
+    pub const fn new() -> Self {
+        Self {
+            signal_profile: SignalProfile::Incipio,
+            kill_timeout: Duration::from_secs(5),
+            emergency_shell: None,
+            default_target: Target::Graphical,
+            ttys: Ttys::new(),
+        }
+    }
+
+    /// Reads [`CONFIG_PATH`], if it exists.
+    ///
+    /// Since a typo must never keep the system from booting, invalid
+    /// lines are logged and skipped, keeping their defaults.
+    pub fn load() -> Self {
+        let mut config = Self::new();
+
+        let mut mapping = match FileMapping::open(CONFIG_PATH) {
+            Ok(mapping) => mapping,
+            Err(Error::Errno(Errno::ENOENT))
+            | Err(Error::UnexpectedEmptyFile) => return config,
+            Err(err) => {
+                libc_eprintln!(
+                    "Failed to read {:?}: {}",
+                    CONFIG_PATH,
+                    err.description()
+                );
+                return config;
+            }
+        };
+
+        config.parse(mapping.as_slice());
+
+        if let Err(err) = mapping.close() {
+            libc_eprintln!(
+                "Failed to close {:?}: {}",
+                CONFIG_PATH,
+                err.description()
+            );
+        }
+
+        config
+    }
+
+    /// The program run on the console when booting fails
+    pub fn emergency_shell(&self) -> &CStr {
+        match &self.emergency_shell {
+            Some(shell) => shell.as_cstr(),
+            None => DEFAULT_SHELL,
+        }
+    }
+
+    fn parse(&mut self, contents: &[u8]) {
+        let mut pairs = KeyValues::new(contents);
+
+        while let Some(pair) = pairs.next() {
+            let result = pair.and_then(|(key, value)| match key {
+                b"signals" => {
+                    self.signal_profile =
+                        SignalProfile::from_name(value)
+                            .ok_or(Error::InvalidConfigValue)?;
+                    Ok(())
+                }
+                b"kill_timeout" => {
+                    let secs = parse_number(value)
+                        .ok_or(Error::InvalidConfigValue)?;
+                    self.kill_timeout =
+                        Duration::from_secs(secs.into());
+                    Ok(())
+                }
+                b"emergency_shell" => {
+                    self.emergency_shell =
+                        Some(ShellPath::from_bytes(value)?);
+                    Ok(())
+                }
+                b"default_target" => {
+                    self.default_target = Target::from_name(value)
+                        .ok_or(Error::InvalidConfigValue)?;
+                    Ok(())
+                }
+                b"tty" => self.ttys.push(Tty::parse(value)?),
+                _ => Err(Error::UnknownConfigKey),
+            });
+
+            if let Err(err) = result {
+                libc_eprintln!(
+                    "{:?}, line {}: {}",
+                    CONFIG_PATH,
+                    pairs.line_number(),
+                    err.description()
+                );
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,274 @@
+use core::{ffi::c_int, fmt::Write, time::Duration};
+
+use libc_print::libc_eprintln;
+use nix::{
+    errno::Errno,
+    sys::{
+        socket::{
+            accept4, bind, listen, recv, send, socket, AddressFamily,
+            MsgFlags, SockFlag, SockType, UnixAddr,
+        },
+        stat::{fchmodat, FchmodatFlags, Mode},
+    },
+    unistd::{close, mkdir, unlink},
+};
+
+use self::protocol::{
+    Command, Request, Status, MAX_REQUEST_SIZE, MAX_RESPONSE_SIZE,
+    SOCKET_DIRECTORY, SOCKET_PATH,
+};
+use crate::{
+    fixed::FixedCStr, power::PowerAction, signal::Action,
+    supervisor::Supervisor, target::Target, utils::monotonic_time,
+    Error,
+};
+
+/// Sends requests through the control socket
+pub mod client;
+/// The protocol spoken over the control socket
+pub mod protocol;
+
+/// The maximum amount of clients connected at once. Any client
+/// beyond that is disconnected right away.
+pub const MAX_CLIENTS: usize = 4;
+
+/// How long a client has to send its request after connecting,
+/// before it's disconnected to free up its slot
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The human-readable part of a response
+type Message = FixedCStr<{ MAX_RESPONSE_SIZE - 1 }>;
+
+/// The Unix domain socket through which `incipioctl` talks to us.
+pub struct ControlServer {
+    listener: c_int,
+    clients: [Option<Client>; MAX_CLIENTS],
+}
+
+/// A connected client that hasn't sent its request yet
+#[derive(Clone, Copy)]
+struct Client {
+    fd: c_int,
+    /// When the client gets disconnected if it's still silent
+    deadline: Duration,
+}
+
+impl ControlServer {
+    /// Creates the control socket at [`SOCKET_PATH`] and starts
+    /// listening on it.
+    pub fn bind() -> crate::Result<Self> {
+        // Only root may talk to incipio
+        match mkdir(SOCKET_DIRECTORY, Mode::S_IRWXU) {
+            Ok(()) | Err(Errno::EEXIST) => {}
+            Err(errno) => return Err(errno.into()),
+        }
+
+        // A socket left behind by a previous incipio would make bind
+        // fail with EADDRINUSE
+        match unlink(SOCKET_PATH) {
+            Ok(()) | Err(Errno::ENOENT) => {}
+            Err(errno) => return Err(errno.into()),
+        }
+
+        let listener = socket(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            None,
+        )?;
+
+        let start_listening = || {
+            bind(listener, &UnixAddr::new(SOCKET_PATH)?)?;
+            fchmodat(
+                None,
+                SOCKET_PATH,
+                Mode::S_IRUSR | Mode::S_IWUSR,
+                FchmodatFlags::FollowSymlink,
+            )?;
+            listen(listener, MAX_CLIENTS)
+        };
+
+        if let Err(errno) = start_listening() {
+            let _ = close(listener);
+            return Err(errno.into());
+        }
+
+        Ok(Self {
+            listener,
+            clients: [None; MAX_CLIENTS],
+        })
+    }
+
+    /// The file descriptors that must be polled for reading: the
+    /// listening socket followed by one per client slot. Empty slots
+    /// are -1, which `poll` ignores.
+    pub fn raw_fds(&self) -> [c_int; MAX_CLIENTS + 1] {
+        let mut fds = [-1; MAX_CLIENTS + 1];
+        fds[0] = self.listener;
+
+        for (fd, client) in fds[1..].iter_mut().zip(self.clients) {
+            *fd = client.map_or(-1, |client| client.fd);
+        }
+
+        fds
+    }
+
+    /// Handles activity on `fd`, one of [`ControlServer::raw_fds`].
+    ///
+    /// Returns the action the client asked for, if it's one that
+    /// must be run by the event loop itself.
+    pub fn handle(
+        &mut self,
+        fd: c_int,
+        supervisor: &mut Supervisor,
+    ) -> Option<Action> {
+        if fd == self.listener {
+            self.accept();
+            return None;
+        }
+
+        let slot = self.clients.iter_mut().find(|client| {
+            client.is_some_and(|client| client.fd == fd)
+        })?;
+        *slot = None;
+
+        // Every connection carries a single request
+        let action = respond(fd, supervisor);
+        let _ = close(fd);
+
+        action
+    }
+
+    fn accept(&mut self) {
+        let client = match accept4(
+            self.listener,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+        ) {
+            Ok(client) => client,
+            // The client gave up before we got to it
+            Err(Errno::EAGAIN | Errno::ECONNABORTED) => return,
+            Err(errno) => {
+                libc_eprintln!("Failed to accept client: {}", errno);
+                return;
+            }
+        };
+
+        match self.clients.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Client {
+                    fd: client,
+                    deadline: monotonic_time() + REQUEST_TIMEOUT,
+                })
+            }
+            None => {
+                libc_eprintln!("Too many control clients");
+                let _ = close(client);
+            }
+        }
+    }
+
+    /// Disconnects the clients that didn't send their request in
+    /// time, so that they can't keep others from connecting.
+    pub fn drop_idle_clients(&mut self) {
+        let now = monotonic_time();
+
+        for slot in &mut self.clients {
+            if let Some(client) =
+                slot.take_if(|client| client.deadline <= now)
+            {
+                libc_eprintln!("Control client timed out");
+                let _ = close(client.fd);
+            }
+        }
+    }
+
+    /// How long until the next client times out, if any is
+    /// connected.
+    pub fn time_until_next_timeout(&self) -> Option<Duration> {
+        let now = monotonic_time();
+
+        self.clients
+            .iter()
+            .flatten()
+            .map(|client| client.deadline)
+            .min()
+            .map(|deadline| deadline.saturating_sub(now))
+    }
+}
+
+/// Reads the request of the client connected through `fd`, runs it
+/// and sends back the response.
+fn respond(fd: c_int, supervisor: &mut Supervisor) -> Option<Action> {
+    let mut request = [0; MAX_REQUEST_SIZE];
+    let len = match recv(fd, &mut request, MsgFlags::MSG_DONTWAIT) {
+        Ok(len) => len,
+        Err(errno) => {
+            libc_eprintln!("Failed to read request: {}", errno);
+            return None;
+        }
+    };
+
+    let mut message = Message::new();
+    let (status, action) = match Request::decode(&request[..len]) {
+        Some(request) => execute(request, supervisor, &mut message),
+        None => {
+            let _ = message.write_str("malformed request");
+            (Status::Error, None)
+        }
+    };
+
+    let mut response = [0; MAX_RESPONSE_SIZE];
+    let message = message.as_bytes();
+    response[0] = status as u8;
+    response[1..=message.len()].copy_from_slice(message);
+
+    if let Err(errno) =
+        send(fd, &response[..=message.len()], MsgFlags::empty())
+    {
+        libc_eprintln!("Failed to send response: {}", errno);
+    }
+
+    action
+}
+
+fn execute(
+    request: Request,
+    supervisor: &mut Supervisor,
+    message: &mut Message,
+) -> (Status, Option<Action>) {
+    let argument = request.argument;
+
+    let result = match request.command {
+        Command::Status => supervisor
+            .write_status(message)
+            .map_err(|_| Error::WriteToString),
+        Command::Start => supervisor.start_service(argument),
+        Command::Stop => supervisor.stop_service(argument),
+        Command::Restart => supervisor.restart_service(argument),
+        Command::PowerOff => {
+            let action = Action::Shutdown(PowerAction::PowerOff);
+            return (Status::Ok, Some(action));
+        }
+        Command::Reboot => {
+            let action = Action::Shutdown(PowerAction::Reboot);
+            return (Status::Ok, Some(action));
+        }
+        Command::Halt => {
+            let action = Action::Shutdown(PowerAction::Halt);
+            return (Status::Ok, Some(action));
+        }
+        Command::Rescue => return (Status::Ok, Some(Action::Rescue)),
+        Command::Target => Target::from_name(argument)
+            .map(|target| supervisor.switch_target(target))
+            .ok_or(Error::UnknownTarget),
+    };
+
+    match result {
+        Ok(()) => (Status::Ok, None),
+        Err(err) => {
+            message.clear();
+            let _ = message.write_str(err.description());
+            (Status::Error, None)
+        }
+    }
+}
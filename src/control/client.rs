@@ -0,0 +1,52 @@
+// This module is shared between incipio and `incipioctl`, so it
+// must only depend on `core`, `nix` and the protocol module.
+
+use nix::{
+    errno::Errno,
+    sys::socket::{
+        connect, recv, send, socket, AddressFamily, MsgFlags,
+        SockFlag, SockType, UnixAddr,
+    },
+    unistd::close,
+};
+
+use super::protocol::{
+    Request, Status, MAX_REQUEST_SIZE, MAX_RESPONSE_SIZE, SOCKET_PATH,
+};
+
+/// Sends `request` to incipio through its control socket and waits
+/// for the response, which gets written into `response`.
+///
+/// Returns whether the request succeeded along with the message
+/// incipio sent back.
+pub fn send_request<'a>(
+    request: &Request,
+    response: &'a mut [u8; MAX_RESPONSE_SIZE],
+) -> nix::Result<(Status, &'a [u8])> {
+    let mut buf = [0; MAX_REQUEST_SIZE];
+    let request =
+        request.encode(&mut buf).ok_or(Errno::ENAMETOOLONG)?;
+
+    let fd = socket(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+
+    let mut exchange = || {
+        connect(fd, &UnixAddr::new(SOCKET_PATH)?)?;
+        send(fd, request, MsgFlags::empty())?;
+        recv(fd, response, MsgFlags::empty())
+    };
+
+    let result = exchange();
+    let _ = close(fd);
+    let len = result?;
+
+    let (&status, message) =
+        response[..len].split_first().ok_or(Errno::EBADMSG)?;
+    let status = Status::from_byte(status).ok_or(Errno::EBADMSG)?;
+
+    Ok((status, message))
+}
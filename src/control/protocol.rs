@@ -0,0 +1,243 @@
+// This module is shared between incipio and `incipioctl`, so it
+// must only depend on `core` and `cstr`.
+
+use core::ffi::CStr;
+
+use cstr::cstr;
+
+/// The directory holding the control socket
+pub static SOCKET_DIRECTORY: &CStr = cstr!("/run/incipio");
+
+/// Where incipio listens for requests
+pub static SOCKET_PATH: &CStr = cstr!("/run/incipio/control");
+
+/// The longest argument, e.g. a service name, a request can carry
+pub const MAX_NAME_LEN: usize = 31;
+
+/// The size of the largest possible request
+pub const MAX_REQUEST_SIZE: usize = 2 + MAX_NAME_LEN;
+
+/// The size of the largest possible response
+pub const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// What a client asks incipio to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Command {
+    /// List the supervised services and their states
+    Status = 1,
+    /// Start a service
+    Start = 2,
+    /// Stop a service
+    Stop = 3,
+    /// Stop a service, then start it again
+    Restart = 4,
+    /// Shut down and power off the machine
+    PowerOff = 5,
+    /// Shut down and reboot the machine
+    Reboot = 6,
+    /// Shut down and halt the machine
+    Halt = 7,
+    /// Stop everything and run a shell on the console, starting
+    /// everything again once it exits
+    Rescue = 8,
+    /// Switch to another target
+    Target = 9,
+}
+
+impl Command {
+    const ALL: [Command; 9] = [
+        Command::Status,
+        Command::Start,
+        Command::Stop,
+        Command::Restart,
+        Command::PowerOff,
+        Command::Reboot,
+        Command::Halt,
+        Command::Rescue,
+        Command::Target,
+    ];
+
+    /// The name of the command, as typed on `incipioctl`'s command
+    /// line
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Status => "status",
+            Command::Start => "start",
+            Command::Stop => "stop",
+            Command::Restart => "restart",
+            Command::PowerOff => "poweroff",
+            Command::Reboot => "reboot",
+            Command::Halt => "halt",
+            Command::Rescue => "rescue",
+            Command::Target => "target",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|command| command.name().as_bytes() == name)
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|&command| command as u8 == byte)
+    }
+
+    /// Whether the command takes an argument, which must then be
+    /// part of the request: the name of a service or, for
+    /// [`Command::Target`], of a target
+    pub fn takes_argument(self) -> bool {
+        matches!(
+            self,
+            Command::Start
+                | Command::Stop
+                | Command::Restart
+                | Command::Target
+        )
+    }
+}
+
+/// A request sent by a client.
+///
+/// Every connection carries a single request, sent as a single
+/// `SOCK_SEQPACKET` message made of a [`Command`] byte, the length
+/// of the argument (possibly zero) and the argument itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub command: Command,
+    /// The service or target the command acts on, empty if none
+    pub argument: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Encodes the request into `buf`, returning the bytes to be
+    /// sent, or `None` if the argument is too long.
+    pub fn encode<'b>(
+        &self,
+        buf: &'b mut [u8; MAX_REQUEST_SIZE],
+    ) -> Option<&'b [u8]> {
+        let len = self.argument.len();
+        if len > MAX_NAME_LEN {
+            return None;
+        }
+
+        buf[0] = self.command as u8;
+        buf[1] = len as u8;
+        buf[2..2 + len].copy_from_slice(self.argument);
+
+        Some(&buf[..2 + len])
+    }
+
+    /// Decodes a request, returning `None` if it's malformed.
+    pub fn decode(bytes: &'a [u8]) -> Option<Self> {
+        let (&command, rest) = bytes.split_first()?;
+        let (&len, argument) = rest.split_first()?;
+
+        if argument.len() != len as usize
+            || argument.len() > MAX_NAME_LEN
+        {
+            return None;
+        }
+
+        let command = Command::from_byte(command)?;
+        if command.takes_argument() == argument.is_empty() {
+            return None;
+        }
+
+        Some(Self { command, argument })
+    }
+}
+
+/// Whether a request succeeded.
+///
+/// Every request gets a single response, sent as a single message
+/// made of a `Status` byte followed by a human-readable message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0,
+    Error = 1,
+}
+
+impl Status {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Status::Ok),
+            1 => Some(Status::Error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_requests() {
+        let requests = [
+            Request {
+                command: Command::Start,
+                argument: b"sshd",
+            },
+            Request {
+                command: Command::Status,
+                argument: b"",
+            },
+        ];
+
+        for request in requests {
+            let mut buf = [0; MAX_REQUEST_SIZE];
+            let bytes = request.encode(&mut buf).unwrap();
+
+            assert_eq!(Request::decode(bytes), Some(request));
+        }
+    }
+
+    #[test]
+    fn encodes_command_and_length_first() {
+        let request = Request {
+            command: Command::Target,
+            argument: b"graphical",
+        };
+        let mut buf = [0; MAX_REQUEST_SIZE];
+
+        assert_eq!(
+            request.encode(&mut buf),
+            Some(&b"\x09\x09graphical"[..])
+        );
+    }
+
+    #[test]
+    fn rejects_long_arguments() {
+        let argument = [b'a'; MAX_NAME_LEN + 1];
+        let request = Request {
+            command: Command::Stop,
+            argument: &argument,
+        };
+        let mut buf = [0; MAX_REQUEST_SIZE];
+
+        assert_eq!(request.encode(&mut buf), None);
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        // Empty, unknown command, wrong length
+        assert_eq!(Request::decode(b""), None);
+        assert_eq!(Request::decode(b"\x00\x00"), None);
+        assert_eq!(Request::decode(b"\x02\x05sshd"), None);
+        // Missing and unexpected arguments
+        assert_eq!(Request::decode(b"\x02\x00"), None);
+        assert_eq!(Request::decode(b"\x01\x04sshd"), None);
+    }
+
+    #[test]
+    fn looks_commands_up_by_name() {
+        assert_eq!(
+            Command::from_name(b"poweroff"),
+            Some(Command::PowerOff)
+        );
+        assert_eq!(Command::from_name(b"shutdown"), None);
+    }
+}
@@ -0,0 +1,89 @@
+use core::ffi::CStr;
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    fcntl::{open, OFlag},
+    sys::stat::Mode,
+    unistd::{close, pause, read, write},
+};
+
+use crate::{
+    config::Config,
+    exec::{execv, spawn, SpawnOptions},
+    power::{force_power_action, PowerAction},
+    wait::wait_pid_no_interrupt,
+};
+
+static CONSOLE: &CStr = cstr!("/dev/console");
+
+/// Gives the administrator a shell on the console after a failure
+/// the boot can't recover from on its own.
+///
+/// Once the shell exits, they're asked whether to go on booting, in
+/// which case this returns, or to reboot. Without a console to ask
+/// on, the system reboots.
+pub fn emergency_shell(reason: &str) {
+    libc_eprintln!("{}", reason);
+
+    let config = Config::load();
+    let shell = config.emergency_shell();
+    libc_eprintln!("Starting {:?} on the console", shell);
+
+    if let Err(err) = run_console_shell(shell) {
+        libc_eprintln!(
+            "Failed to start emergency shell: {}",
+            err.description()
+        );
+    }
+
+    if wants_to_continue() {
+        return;
+    }
+
+    if let Err(errno) = force_power_action(PowerAction::Reboot) {
+        libc_eprintln!("Failed to reboot: {}", errno);
+    }
+
+    loop {
+        pause();
+    }
+}
+
+/// Runs `shell` on the console and waits for it to exit
+pub fn run_console_shell(shell: &CStr) -> crate::Result<()> {
+    let options = SpawnOptions {
+        new_session: true,
+        tty: Some(CONSOLE),
+    };
+    let pid = spawn(&options, || execv(shell))?;
+    wait_pid_no_interrupt(pid, None)?;
+
+    Ok(())
+}
+
+/// Asks on the console whether to go on booting rather than reboot
+fn wants_to_continue() -> bool {
+    static PROMPT: &[u8] =
+        b"Press Enter to continue booting, or type reboot: ";
+
+    let Ok(fd) = open(
+        CONSOLE,
+        OFlag::O_RDWR | OFlag::O_NOCTTY,
+        Mode::empty(),
+    ) else {
+        return false;
+    };
+
+    let mut answer = [0; 32];
+    let answer = write(fd, PROMPT)
+        .and_then(|_| read(fd, &mut answer))
+        .map(|len| &answer[..len]);
+    let _ = close(fd);
+
+    match answer {
+        // Nobody is there to answer
+        Ok(b"") | Err(_) => false,
+        Ok(answer) => answer.trim_ascii() != b"reboot",
+    }
+}
@@ -7,6 +7,24 @@ pub enum Error {
     WriteToString,
     MmapFailed,
     NotRunningAsInitSystem,
+    CapacityExceeded,
+    UnexpectedNul,
+    ConfigSyntax,
+    UnknownConfigKey,
+    InvalidConfigValue,
+    MissingCommand,
+    DuplicateService,
+    DependencyCycle,
+    AlwaysRestartedOneshot,
+    UnknownService,
+    ServiceAlreadyRunning,
+    ServiceNotRunning,
+    UnknownTarget,
+    UnknownUser,
+    MissingRoot,
+    DeviceNotFound,
+    MissingMountHelper,
+    MountHelperFailed,
     Errno(Errno),
 }
 
@@ -22,6 +40,48 @@ impl Error {
             Error::MountPointParser => {
                 "failed to parse mount point file"
             }
+            Error::CapacityExceeded => {
+                "a fixed-capacity buffer ran out of space"
+            }
+            Error::UnexpectedNul => {
+                "found a NUL byte in the middle of a string"
+            }
+            Error::ConfigSyntax => {
+                "expected a `key = value` line in configuration file"
+            }
+            Error::UnknownConfigKey => {
+                "unknown key in configuration file"
+            }
+            Error::InvalidConfigValue => {
+                "invalid value in configuration file"
+            }
+            Error::MissingCommand => "service has no command to run",
+            Error::DuplicateService => {
+                "a service with the same name already exists"
+            }
+            Error::DependencyCycle => {
+                "services depend on each other in a cycle"
+            }
+            Error::AlwaysRestartedOneshot => {
+                "oneshot services can't have `restart = always`"
+            }
+            Error::UnknownService => "no service with that name",
+            Error::ServiceAlreadyRunning => {
+                "service is already running"
+            }
+            Error::ServiceNotRunning => "service is not running",
+            Error::UnknownTarget => "no target with that name",
+            Error::UnknownUser => "user not found in /etc/passwd",
+            Error::MissingRoot => {
+                "no root= parameter in the kernel command line"
+            }
+            Error::DeviceNotFound => {
+                "no block device with that UUID or label"
+            }
+            Error::MissingMountHelper => {
+                "no mount helper installed for that filesystem type"
+            }
+            Error::MountHelperFailed => "mount helper failed",
             Error::Errno(errno) => errno.desc(),
         }
     }
@@ -0,0 +1,133 @@
+use core::{ffi::c_int, time::Duration};
+
+use libc_print::libc_eprintln;
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
+
+use crate::{
+    boot::shutdown_system,
+    config::Config,
+    control::{ControlServer, MAX_CLIENTS},
+    rescue::enter_rescue_mode,
+    signal::{Action, SignalReceiver},
+    supervisor::Supervisor,
+};
+
+/// Waits for signals and control requests and runs their actions,
+/// forever.
+///
+/// This is where PID 1 spends its life once the system is booted.
+pub fn run_event_loop(
+    signals: &SignalReceiver,
+    supervisor: &mut Supervisor,
+    mut control: Option<ControlServer>,
+    config: &Config,
+) -> ! {
+    loop {
+        // Restart the processes whose backoff has expired and kill
+        // the ones that won't stop
+        supervisor.handle_timeouts();
+        if let Some(control) = &mut control {
+            control.drop_idle_clients();
+        }
+
+        // The signalfd comes first, followed by the control socket
+        // and its clients. Negative file descriptors are ignored.
+        let mut fds = [-1; MAX_CLIENTS + 2];
+        fds[0] = signals.raw_fd();
+        if let Some(control) = &control {
+            fds[1..].copy_from_slice(&control.raw_fds());
+        }
+
+        let mut poll_fds =
+            fds.map(|fd| PollFd::new(fd, PollFlags::POLLIN));
+
+        // Sleep until something happens or the next timeout is due
+        let control_timeout = control
+            .as_ref()
+            .and_then(ControlServer::time_until_next_timeout);
+        let timeout = match (
+            supervisor.time_until_next_timeout(),
+            control_timeout,
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (timeout, None) | (None, timeout) => timeout,
+        };
+        if !wait_for_events(&mut poll_fds, timeout) {
+            continue;
+        }
+
+        for (fd, poll_fd) in fds.into_iter().zip(poll_fds) {
+            if poll_fd
+                .revents()
+                .is_none_or(|events| events.is_empty())
+            {
+                continue;
+            }
+
+            let action = if fd == signals.raw_fd() {
+                receive_signal(signals)
+            } else {
+                control.as_mut().and_then(|control| {
+                    control.handle(fd, supervisor)
+                })
+            };
+
+            match action {
+                Some(Action::ReapChildren) => supervisor.reap(),
+                Some(Action::Shutdown(action)) => {
+                    shutdown_system(action, config)
+                }
+                Some(Action::Rescue) => {
+                    enter_rescue_mode(supervisor, config)
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Reads the next signal, returning what to do about it
+fn receive_signal(signals: &SignalReceiver) -> Option<Action> {
+    let signal = match signals.read_signal() {
+        Ok(signal) => signal,
+        Err(errno) => {
+            libc_eprintln!("Failed to read signal: {}", errno);
+            return None;
+        }
+    };
+
+    let action = signals.profile().action(signal);
+    if action.is_none() {
+        libc_eprintln!("Ignoring unexpected signal {}", signal);
+    }
+
+    action
+}
+
+/// Waits until one of `poll_fds` has events or until `timeout`
+/// expires.
+///
+/// Returns whether any file descriptor got events.
+fn wait_for_events(
+    poll_fds: &mut [PollFd],
+    timeout: Option<Duration>,
+) -> bool {
+    // A negative timeout means waiting forever
+    let timeout_ms = timeout.map_or(-1, |timeout| {
+        // Round up so that we don't wake up right before the deadline
+        let millis = timeout.as_millis() + 1;
+        c_int::try_from(millis).unwrap_or(c_int::MAX)
+    });
+
+    match poll(poll_fds, timeout_ms) {
+        Ok(ready) => ready > 0,
+        Err(Errno::EINTR) => false,
+        Err(errno) => {
+            libc_eprintln!("Failed to poll: {}", errno);
+            false
+        }
+    }
+}
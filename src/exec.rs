@@ -1,42 +1,135 @@
 use core::{
-    ffi::{c_char, CStr},
+    ffi::{c_char, c_uint, CStr},
     fmt::Debug,
-    ops::Not,
 };
 
 use libc_print::libc_eprintln;
 use nix::{
     errno::Errno,
-    unistd::{fork, ForkResult},
-    NixPath,
+    fcntl::{open, OFlag},
+    libc::{ioctl, syscall, SYS_close_range, TIOCSCTTY},
+    sys::stat::Mode,
+    unistd::{close, dup2, fork, setsid, ForkResult, Pid},
 };
 
-use crate::{utils::NixPathExt, wait::wait_pid_no_interrupt};
+use crate::{
+    fixed::CStrList,
+    signal::{reset_signal_dispositions, unblock_all_signals},
+};
+
+/// A builder for the processes incipio runs
+pub mod command;
 
-/// Sad work-around since a generic `execute` function would likely
-/// have to allocate on the heap.
+/// How a child process is set up before it runs its program.
 ///
-/// Fork and execute through `execv` with an already built command
-/// array.
-pub fn fork_and_execute_command<const N: usize>(
-    commands: [*const c_char; N],
-    should_wait: bool,
-) -> crate::Result<()> {
+/// Whatever the options, children start with the default signal
+/// dispositions, no blocked signals and no file descriptors other
+/// than the standard streams.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpawnOptions<'a> {
+    /// Start a new session, so that the child is no longer part of
+    /// incipio's session and process group
+    pub new_session: bool,
+    /// A terminal to open as the controlling terminal and standard
+    /// streams of the child, which implies a new session
+    pub tty: Option<&'a CStr>,
+}
+
+/// Forks the current process and runs `exec` in the child, which
+/// is expected to replace the child with another program, after
+/// setting the child up according to `options`. Returns the PID of
+/// the child process without waiting for it.
+pub fn spawn(
+    options: &SpawnOptions,
+    exec: impl FnOnce() -> crate::Result<()>,
+) -> crate::Result<Pid> {
     // TODO: Check if we're able to run vfork here
     let fork_result = unsafe { fork()? };
 
     match fork_result {
-        ForkResult::Child => execv_commands(commands)?,
-        ForkResult::Parent { child } => {
-            if should_wait {
-                wait_pid_no_interrupt(child, None)?;
-            }
-        }
+        ForkResult::Child => run_child(options, exec),
+        ForkResult::Parent { child } => Ok(child),
+    }
+}
+
+/// Runs `exec` in a freshly forked child process.
+///
+/// The child must never return to the caller, since it'd go on
+/// running a copy of incipio: if `exec` fails, the child exits with
+/// status 127, like shells do for commands that could not be run.
+fn run_child(
+    options: &SpawnOptions,
+    exec: impl FnOnce() -> crate::Result<()>,
+) -> ! {
+    if let Err(err) = set_up_child(options) {
+        libc_eprintln!(
+            "Failed to set up child process: {}",
+            err.description()
+        );
+    } else if let Err(err) = exec() {
+        libc_eprintln!("Failed to execute: {}", err.description());
+    }
+
+    unsafe { nix::libc::_exit(127) }
+}
+
+/// Undoes what the child inherited from incipio, as described in
+/// [`SpawnOptions`]
+fn set_up_child(options: &SpawnOptions) -> crate::Result<()> {
+    reset_signal_dispositions();
+    unblock_all_signals()?;
+
+    if options.new_session || options.tty.is_some() {
+        setsid()?;
+    }
+
+    if let Some(tty) = options.tty {
+        attach_to_terminal(tty)?;
+    }
+
+    close_inherited_fds();
+
+    Ok(())
+}
+
+/// Makes `tty` the controlling terminal and the standard streams of
+/// the calling process, which must be a session leader.
+fn attach_to_terminal(tty: &CStr) -> crate::Result<()> {
+    let fd = open(
+        tty,
+        OFlag::O_RDWR | OFlag::O_NOCTTY,
+        Mode::empty(),
+    )?;
+
+    // Safety: TIOCSCTTY takes an integer, where 0 means not to steal
+    // the terminal from another session
+    Errno::result(unsafe { ioctl(fd, TIOCSCTTY, 0) })?;
+
+    for stdio in 0..=2 {
+        dup2(fd, stdio)?;
+    }
+    if fd > 2 {
+        close(fd)?;
     }
 
     Ok(())
 }
 
+/// Closes every file descriptor but the standard streams
+fn close_inherited_fds() {
+    // Safety: close_range(2) takes no pointers
+    let closed =
+        unsafe { syscall(SYS_close_range, 3, c_uint::MAX, 0) } == 0;
+
+    // Kernels older than 5.9 lack close_range(2), so we fall back to
+    // closing what would be open under the default limit of 1024
+    if !closed {
+        for fd in 3..1024 {
+            let _ = close(fd);
+        }
+    }
+}
+
 /// Run `execv` with an already built `commands` sequence.
 fn execv_commands<const N: usize>(
     commands: [*const c_char; N],
@@ -47,32 +140,37 @@ fn execv_commands<const N: usize>(
     Errno::result(ret_val).map_err(Into::into).map(|_result| ())
 }
 
-// Run execv without additional arguments
-fn execv(path: &CStr) -> crate::Result<()> {
-    let command = [path.as_ptr(), core::ptr::null()];
-
-    execv_commands(command)
+/// Run `execv` with the given list of arguments, the first of
+/// which is the path to the program.
+pub fn execv_list<const N: usize>(
+    arguments: &CStrList<N>,
+) -> crate::Result<()> {
+    execv_commands(arguments.as_ptrs())
 }
 
-pub fn execute<P: NixPath + Debug + ?Sized>(
-    path: &P,
+/// Run `execve` with the given lists of arguments and environment
+/// variables, so that the program does not inherit our environment.
+pub fn execve_list<const N: usize, const M: usize>(
+    arguments: &CStrList<N>,
+    environment: &CStrList<M>,
 ) -> crate::Result<()> {
-    if path.is_executable().not() {
-        libc_eprintln!("Tried to run {:?} but it doesn't exist or is not executable", path);
-    }
+    let arguments = arguments.as_ptrs();
+    let environment = environment.as_ptrs();
 
-    let execute_path = |path: &CStr| {
-        let fork_result = unsafe { fork()? };
+    let ret_val = unsafe {
+        nix::libc::execve(
+            arguments[0],
+            arguments.as_ptr(),
+            environment.as_ptr(),
+        )
+    };
 
-        match fork_result {
-            ForkResult::Child => execv(path)?,
-            ForkResult::Parent { child } => {
-                wait_pid_no_interrupt(child, None)?;
-            }
-        }
+    Errno::result(ret_val).map_err(Into::into).map(|_result| ())
+}
 
-        Ok(()) as crate::Result<()>
-    };
+/// Run `execv` without additional arguments
+pub fn execv(path: &CStr) -> crate::Result<()> {
+    let command = [path.as_ptr(), core::ptr::null()];
 
-    path.with_nix_path(execute_path)?
+    execv_commands(command)
 }
@@ -0,0 +1,186 @@
+use core::ffi::c_int;
+
+use nix::{
+    errno::Errno,
+    libc::{setpriority, PRIO_PROCESS},
+    sys::{
+        stat::{umask, Mode},
+        wait::WaitStatus,
+    },
+    unistd::{chdir, setgid, setgroups, setuid, Pid},
+};
+
+use super::{execv_list, execve_list, spawn, SpawnOptions};
+use crate::{
+    fixed::{CStrList, FixedCStr},
+    passwd::{lookup_user, Credentials},
+    wait::wait_pid_no_interrupt,
+};
+
+/// The size of the buffer that holds the arguments of a command
+pub const ARGUMENTS_SIZE: usize = 512;
+
+/// The `argv` of a command
+pub type Arguments = CStrList<ARGUMENTS_SIZE>;
+
+/// The environment variables of a command, as `KEY=value` strings
+pub type Environment = CStrList<512>;
+
+/// A program to run and how to run it, like
+/// `std::process::Command` but kept in fixed-capacity buffers.
+///
+/// Unless told otherwise, the program inherits the environment,
+/// working directory, credentials, umask and priority of incipio.
+/// See [`SpawnOptions`] for what it never inherits.
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    /// The program, followed by its arguments
+    arguments: Arguments,
+    /// `None` to inherit the environment of incipio
+    environment: Option<Environment>,
+    directory: Option<FixedCStr<256>>,
+    /// `None` to run as the user and groups of incipio
+    credentials: Option<Credentials>,
+    umask: Option<Mode>,
+    nice: Option<c_int>,
+    new_session: bool,
+    tty: Option<FixedCStr<64>>,
+}
+
+impl Command {
+    /// A command running `program`, which must be a path since
+    /// `PATH` is not searched
+    pub fn new(program: &[u8]) -> crate::Result<Self> {
+        let mut arguments = Arguments::new();
+        arguments.push(program)?;
+
+        Ok(Self {
+            arguments,
+            environment: None,
+            directory: None,
+            credentials: None,
+            umask: None,
+            nice: None,
+            new_session: false,
+            tty: None,
+        })
+    }
+
+    pub fn arg(
+        &mut self,
+        argument: &[u8],
+    ) -> crate::Result<&mut Self> {
+        self.arguments.push(argument)?;
+        Ok(self)
+    }
+
+    /// Adds a `KEY=value` environment variable. Once any is added,
+    /// the environment of incipio is no longer inherited.
+    pub fn env(
+        &mut self,
+        variable: &[u8],
+    ) -> crate::Result<&mut Self> {
+        self.environment
+            .get_or_insert_with(Environment::new)
+            .push(variable)?;
+        Ok(self)
+    }
+
+    /// Runs the program from `directory`
+    pub fn current_dir(
+        &mut self,
+        directory: &[u8],
+    ) -> crate::Result<&mut Self> {
+        self.directory = Some(FixedCStr::from_bytes(directory)?);
+        Ok(self)
+    }
+
+    /// Runs the program as `user`, which is a user name, a UID or a
+    /// `uid:gid` pair, as understood by [`lookup_user`].
+    ///
+    /// The user is looked up right away, so that a user that
+    /// doesn't exist is an error here rather than in the child.
+    pub fn user(&mut self, user: &[u8]) -> crate::Result<&mut Self> {
+        self.credentials = Some(lookup_user(user)?);
+        Ok(self)
+    }
+
+    pub fn umask(&mut self, mask: Mode) -> &mut Self {
+        self.umask = Some(mask);
+        self
+    }
+
+    /// Runs the program with the niceness `nice`, from -20 (the
+    /// highest priority) to 19
+    pub fn nice(&mut self, nice: c_int) -> &mut Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Runs the program in a session of its own
+    pub fn new_session(&mut self) -> &mut Self {
+        self.new_session = true;
+        self
+    }
+
+    /// Runs the program on the terminal `tty`, e.g. `/dev/tty1`,
+    /// in a session of its own
+    pub fn tty(&mut self, tty: &[u8]) -> crate::Result<&mut Self> {
+        self.tty = Some(FixedCStr::from_bytes(tty)?);
+        Ok(self)
+    }
+
+    /// Starts the program, returning its PID without waiting for it
+    pub fn spawn(&self) -> crate::Result<Pid> {
+        let options = SpawnOptions {
+            new_session: self.new_session,
+            tty: self.tty.as_ref().map(FixedCStr::as_cstr),
+        };
+
+        spawn(&options, || self.exec())
+    }
+
+    /// Runs the program and waits for it to exit
+    pub fn status(&self) -> crate::Result<WaitStatus> {
+        let pid = self.spawn()?;
+
+        Ok(wait_pid_no_interrupt(pid, None)?)
+    }
+
+    /// Sets up the calling process and then replaces it with the
+    /// program, only returning if something failed.
+    fn exec(&self) -> crate::Result<()> {
+        if let Some(mask) = self.umask {
+            umask(mask);
+        }
+
+        // Raising the priority takes root, so it must happen before
+        // the user is changed
+        if let Some(nice) = self.nice {
+            // Safety: setpriority(2) takes no pointers
+            Errno::result(unsafe {
+                setpriority(PRIO_PROCESS, 0, nice)
+            })?;
+        }
+
+        if let Some(credentials) = &self.credentials {
+            // Groups must be changed while we're still root, replacing
+            // those of incipio
+            setgroups(credentials.groups())?;
+            setgid(credentials.gid)?;
+            setuid(credentials.uid)?;
+        }
+
+        // Only now, so that the user must be allowed into it
+        if let Some(directory) = &self.directory {
+            chdir(directory.as_cstr())?;
+        }
+
+        match &self.environment {
+            Some(environment) => {
+                execve_list(&self.arguments, environment)
+            }
+            None => execv_list(&self.arguments),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use core::{
+    ffi::{c_char, CStr},
+    fmt,
+};
+
+use crate::Error;
+
+/// The maximum amount of strings a [`CStrList`] can hold.
+pub const MAX_LIST_STRINGS: usize = 31;
+
+/// A NUL-terminated string stored inline, holding at most `N - 1`
+/// bytes of content.
+#[derive(Clone, Copy)]
+pub struct FixedCStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedCStr<N> {
+    /// Creates an empty string
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Copies `bytes` into a new string.
+    ///
+    /// Fails if `bytes` contains a NUL byte or if it does not fit.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        let mut string = Self::new();
+        string.push(bytes)?;
+
+        Ok(string)
+    }
+
+    /// Copies `bytes` to the end of this string.
+    ///
+    /// Fails if `bytes` contains a NUL byte or if it does not fit.
+    pub fn push(&mut self, bytes: &[u8]) -> crate::Result<()> {
+        if bytes.contains(&0) {
+            return Err(Error::UnexpectedNul);
+        }
+
+        // Leave room for the NUL terminator
+        let end = self.len + bytes.len();
+        if end >= N {
+            return Err(Error::CapacityExceeded);
+        }
+
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+
+        Ok(())
+    }
+
+    /// Empties this string
+    pub fn clear(&mut self) {
+        self.buf[..self.len].fill(0);
+        self.len = 0;
+    }
+
+    /// The contents of this string, without the NUL terminator
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn as_cstr(&self) -> &CStr {
+        // Safety: `push` rejects interior NUL bytes and the rest of
+        // the buffer is always zeroed
+        unsafe {
+            CStr::from_bytes_with_nul_unchecked(
+                &self.buf[..=self.len],
+            )
+        }
+    }
+}
+
+impl<const N: usize> Default for FixedCStr<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedCStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_cstr().fmt(f)
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedCStr<N> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.push(string.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// A list of NUL-terminated strings packed one after another into a
+/// buffer of `N` bytes, such as the `argv` or `envp` of a process.
+#[derive(Clone, Copy)]
+pub struct CStrList<const N: usize> {
+    buf: [u8; N],
+    used: usize,
+    count: usize,
+}
+
+impl<const N: usize> CStrList<N> {
+    /// Creates an empty list
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            used: 0,
+            count: 0,
+        }
+    }
+
+    /// Copies `bytes` to the end of the list.
+    ///
+    /// Fails if `bytes` contains a NUL byte or if the list is full.
+    pub fn push(&mut self, bytes: &[u8]) -> crate::Result<()> {
+        if bytes.contains(&0) {
+            return Err(Error::UnexpectedNul);
+        }
+
+        let end = self.used + bytes.len();
+        if end >= N || self.count == MAX_LIST_STRINGS {
+            return Err(Error::CapacityExceeded);
+        }
+
+        self.buf[self.used..end].copy_from_slice(bytes);
+        self.buf[end] = 0;
+        self.used = end + 1;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterates over the strings of this list, in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = &CStr> {
+        self.buf[..self.used]
+            .split_inclusive(|&byte| byte == 0)
+            .map(|bytes| {
+                // Safety: every string was pushed alongside a single
+                // NUL terminator
+                unsafe { CStr::from_bytes_with_nul_unchecked(bytes) }
+            })
+    }
+
+    /// Builds a NULL-terminated array of pointers to the strings of
+    /// this list, in the format expected by `execv` and friends.
+    ///
+    /// The pointers are only valid while `self` is alive and
+    /// unchanged.
+    pub fn as_ptrs(&self) -> [*const c_char; MAX_LIST_STRINGS + 1] {
+        let mut pointers = [core::ptr::null(); MAX_LIST_STRINGS + 1];
+
+        for (pointer, string) in pointers.iter_mut().zip(self.iter())
+        {
+            *pointer = string.as_ptr();
+        }
+
+        pointers
+    }
+}
+
+impl<const N: usize> fmt::Debug for CStrList<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<const N: usize> Default for CStrList<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,11 +2,13 @@ use core::{ffi::CStr, ptr::NonNull};
 
 use cstr::cstr;
 use nix::{
+    errno::Errno,
     libc::{endmntent, getmntent, setmntent, FILE},
+    sys::stat::stat,
     NixPath,
 };
 
-use crate::Error;
+use crate::{fixed::FixedCStr, Error};
 
 const READ_FLAG: &CStr = cstr!("r");
 
@@ -19,8 +21,13 @@ impl MountPointParser {
         let stream = path.with_nix_path(|path| unsafe {
             setmntent(path.as_ptr(), READ_FLAG.as_ptr())
         })?;
-        let stream =
-            NonNull::new(stream).ok_or(Error::MountPointParser)?;
+        let stream = NonNull::new(stream).ok_or_else(|| {
+            // setmntent fails like fopen does, e.g. with ENOENT
+            match Errno::last() {
+                Errno::UnknownErrno => Error::MountPointParser,
+                errno => errno.into(),
+            }
+        })?;
 
         Ok(Self { stream })
     }
@@ -38,16 +45,39 @@ pub struct Entry {
 }
 
 impl Entry {
-    /// The name of the path this entry represents
-    pub fn path(&self) -> &CStr {
+    fn raw(&self) -> &nix::libc::mntent {
         // Safety: this (non-null) pointer is provenient of a
         // succesfull call to `setmntent`, which manages the
         // entire lifetime of this pointer until the stream is
         // closed with `endmntent`.
-        unsafe {
-            let mnt_dir_ptr = self.entry_ptr.as_ref().mnt_dir;
-            CStr::from_ptr(mnt_dir_ptr)
-        }
+        unsafe { self.entry_ptr.as_ref() }
+    }
+
+    /// The name of the path this entry represents
+    pub fn path(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.raw().mnt_dir) }
+    }
+
+    /// The device or filesystem being mounted, e.g. `/dev/sda1` or
+    /// `UUID=...`
+    pub fn fsname(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.raw().mnt_fsname) }
+    }
+
+    /// The type of the filesystem, e.g. `ext4` or `tmpfs`
+    pub fn filesystem_type(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.raw().mnt_type) }
+    }
+
+    /// The comma-separated mount options
+    pub fn options(&self) -> &CStr {
+        unsafe { CStr::from_ptr(self.raw().mnt_opts) }
+    }
+
+    /// The order in which filesystems are checked at boot, where 0
+    /// means the filesystem is not checked at all
+    pub fn pass_number(&self) -> i32 {
+        self.raw().mnt_passno
     }
 }
 
@@ -66,3 +96,21 @@ impl Iterator for MountPointParser {
         Entry::from_raw(entry_ptr)
     }
 }
+
+/// Whether `path` is a mount point, i.e. whether it's on a different
+/// filesystem than its parent directory.
+///
+/// Bind mounts of a directory onto another one of the same
+/// filesystem are not detected.
+pub fn is_mount_point(path: &CStr) -> crate::Result<bool> {
+    let mut parent = FixedCStr::<256>::from_bytes(path.to_bytes())?;
+    parent.push(b"/..")?;
+
+    let status = stat(path)?;
+    let parent_status = stat(parent.as_cstr())?;
+
+    // `/..` is `/` itself, which is always a mount point
+    let is_root = status.st_ino == parent_status.st_ino;
+
+    Ok(status.st_dev != parent_status.st_dev || is_root)
+}
@@ -0,0 +1,114 @@
+use core::ffi::CStr;
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    libc::{reboot, LINUX_REBOOT_CMD_RESTART},
+    sys::wait::WaitStatus,
+    unistd::pause,
+};
+
+use crate::{
+    emergency::emergency_shell,
+    exec::command::Command,
+    fs::{is_mount_point, Entry},
+    utils::NixPathExt,
+};
+
+static ROOT: &CStr = cstr!("/");
+static FSCK: &CStr = cstr!("/usr/bin/fsck");
+static SBIN_FSCK: &CStr = cstr!("/sbin/fsck");
+
+// Bits of the exit status of fsck, see fsck(8)
+const ERRORS_CORRECTED: i32 = 1;
+const REBOOT_REQUIRED: i32 = 2;
+const ERRORS_UNCORRECTED: i32 = 4;
+const OPERATIONAL_ERROR: i32 = 8;
+
+fn fsck_binary() -> Option<&'static CStr> {
+    [FSCK, SBIN_FSCK]
+        .into_iter()
+        .find(|path| path.is_executable())
+}
+
+/// Runs `fsck -a` on the filesystem of `entry`, if its pass number
+/// asks for it to be checked and it's not mounted yet. Root is the
+/// exception, since it's checked while still mounted read-only.
+///
+/// Never returns if repairing root requires a reboot. Starts an
+/// emergency shell if the check finds errors it can't correct by
+/// itself.
+pub fn check_filesystem(entry: &Entry) {
+    if entry.pass_number() <= 0 {
+        return;
+    }
+
+    let is_root = entry.path() == ROOT;
+    if !is_root && is_mount_point(entry.path()).unwrap_or(false) {
+        return;
+    }
+
+    let Some(fsck) = fsck_binary() else {
+        libc_eprintln!("fsck not found, skipping filesystem checks");
+        return;
+    };
+
+    let device = entry.fsname();
+    let status = match run_fsck(fsck, device) {
+        Ok(status) => status,
+        Err(err) => {
+            libc_eprintln!(
+                "Failed to check {:?}: {}",
+                device,
+                err.description()
+            );
+            return;
+        }
+    };
+
+    if status & ERRORS_UNCORRECTED != 0 {
+        // Whatever the administrator did in the shell supersedes
+        // the rest of the status
+        emergency_shell("fsck found errors it could not correct");
+        return;
+    }
+
+    // Only root is mounted already, so the kernel may be holding on
+    // to what fsck has just repaired
+    if status & REBOOT_REQUIRED != 0 && is_root {
+        libc_eprintln!("fsck modified {:?}, rebooting", device);
+
+        // No sync: whatever is cached for a filesystem fsck has just
+        // repaired under our feet must not be written back
+        unsafe { reboot(LINUX_REBOOT_CMD_RESTART) };
+        loop {
+            pause();
+        }
+    }
+
+    if status & (ERRORS_CORRECTED | REBOOT_REQUIRED) != 0 {
+        libc_eprintln!("fsck corrected errors on {:?}", device);
+    } else if status != 0 {
+        // e.g. no fsck.* helper for this filesystem type
+        libc_eprintln!(
+            "fsck could not check {:?}, exit status {}",
+            device,
+            status
+        );
+    }
+}
+
+/// Runs `fsck -a device`, returning its exit status
+fn run_fsck(fsck: &CStr, device: &CStr) -> crate::Result<i32> {
+    let mut command = Command::new(fsck.to_bytes())?;
+    command.arg(b"-a")?.arg(device.to_bytes())?;
+
+    let status = match command.status()? {
+        WaitStatus::Exited(_, status) => status,
+        // Killed by a signal: it's unknown what state it left the
+        // filesystem in, but it's no reason to stop the boot
+        _ => OPERATIONAL_ERROR,
+    };
+
+    Ok(status)
+}
@@ -0,0 +1,230 @@
+use crate::supervisor::MAX_SUPERVISED;
+
+// Each node's edges are stored as the bits of an u64
+const _: () = assert!(MAX_SUPERVISED <= u64::BITS as usize);
+
+/// A fixed-size directed graph of at most [`MAX_SUPERVISED`] nodes,
+/// where an edge from `a` to `b` means that `a` must start before
+/// `b` does.
+pub struct DependencyGraph {
+    /// Bit `b` of `edges[a]` is set if there's an edge from `a` to `b`
+    edges: [u64; MAX_SUPERVISED],
+    /// Bit `a` is set if node `a` is part of the graph
+    nodes: u64,
+}
+
+impl DependencyGraph {
+    pub const fn new() -> Self {
+        Self {
+            edges: [0; MAX_SUPERVISED],
+            nodes: 0,
+        }
+    }
+
+    pub fn add_node(&mut self, node: usize) {
+        self.nodes |= 1 << node;
+    }
+
+    /// Adds an edge meaning that `before` must start before `after`
+    pub fn add_edge(&mut self, before: usize, after: usize) {
+        self.edges[before] |= 1 << after;
+    }
+
+    /// Sorts the nodes of the graph so that every node comes after
+    /// all of the nodes it has edges from (Kahn's algorithm).
+    ///
+    /// Nodes that are part of a cycle, or that depend on a node in a
+    /// cycle, are left out of the order.
+    pub fn topological_order(&self) -> TopologicalOrder {
+        let mut in_degree = [0_u32; MAX_SUPERVISED];
+        for (before, edges) in self.edges.iter().enumerate() {
+            if self.contains(before) {
+                for (after, degree) in
+                    in_degree.iter_mut().enumerate()
+                {
+                    if edges & (1 << after) != 0 {
+                        *degree += 1;
+                    }
+                }
+            }
+        }
+
+        let mut order = TopologicalOrder {
+            nodes: [0; MAX_SUPERVISED],
+            len: 0,
+        };
+
+        // Nodes with no pending edges, yet to be visited
+        let mut ready: u64 = (0..MAX_SUPERVISED)
+            .filter(|&node| {
+                self.contains(node) && in_degree[node] == 0
+            })
+            .fold(0, |ready, node| ready | 1 << node);
+
+        while ready != 0 {
+            let node = ready.trailing_zeros() as usize;
+            ready &= !(1 << node);
+
+            order.nodes[order.len] = node;
+            order.len += 1;
+
+            for (after, degree) in in_degree.iter_mut().enumerate() {
+                if self.edges[node] & (1 << after) != 0 {
+                    *degree -= 1;
+                    if *degree == 0 && self.contains(after) {
+                        ready |= 1 << after;
+                    }
+                }
+            }
+        }
+
+        order
+    }
+
+    /// The nodes with an edge from `node`, i.e. the ones that must
+    /// start after it does
+    pub fn dependents(
+        &self,
+        node: usize,
+    ) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_SUPERVISED).filter(move |&after| {
+            self.edges[node] & (1 << after) != 0
+        })
+    }
+
+    /// A cycle that keeps `node` out of `order`, as a bit set of the
+    /// nodes in it, or 0 if `node` is not stuck behind a cycle.
+    pub fn cycle_behind(
+        &self,
+        node: usize,
+        order: &TopologicalOrder,
+    ) -> u64 {
+        // Every node left out of the order has an edge from another
+        // one left out, so walking those edges backwards eventually
+        // goes around a cycle
+        let mut path = [0; MAX_SUPERVISED];
+        let mut current = node;
+
+        // There are only so many nodes to visit before one repeats
+        for len in 0..=MAX_SUPERVISED {
+            if !self.contains(current) || order.contains(current) {
+                return 0;
+            }
+
+            if let Some(start) = path[..len]
+                .iter()
+                .position(|&visited| visited == current)
+            {
+                return path[start..len]
+                    .iter()
+                    .fold(0, |cycle, &node| cycle | 1 << node);
+            }
+            path[len] = current;
+
+            let Some(before) = (0..MAX_SUPERVISED).find(|&before| {
+                self.contains(before)
+                    && !order.contains(before)
+                    && self.edges[before] & (1 << current) != 0
+            }) else {
+                return 0;
+            };
+            current = before;
+        }
+
+        0
+    }
+
+    fn contains(&self, node: usize) -> bool {
+        self.nodes & (1 << node) != 0
+    }
+}
+
+impl Default for DependencyGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The nodes of a [`DependencyGraph`], in dependency order
+pub struct TopologicalOrder {
+    nodes: [usize; MAX_SUPERVISED],
+    len: usize,
+}
+
+impl TopologicalOrder {
+    pub fn as_slice(&self) -> &[usize] {
+        &self.nodes[..self.len]
+    }
+
+    /// Whether `node` could be ordered, i.e. it's not stuck behind
+    /// a dependency cycle
+    pub fn contains(&self, node: usize) -> bool {
+        self.as_slice().contains(&node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(
+        nodes: &[usize],
+        edges: &[(usize, usize)],
+    ) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for &node in nodes {
+            graph.add_node(node);
+        }
+        for &(before, after) in edges {
+            graph.add_edge(before, after);
+        }
+        graph
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        let graph = graph(&[0, 1, 2, 3], &[(2, 0), (3, 2), (1, 0)]);
+
+        assert_eq!(
+            graph.topological_order().as_slice(),
+            [1, 3, 2, 0]
+        );
+    }
+
+    #[test]
+    fn ignores_edges_from_missing_nodes() {
+        // Node 5 is not part of the graph, e.g. an unknown `after`
+        let graph = graph(&[0, 1], &[(5, 0), (0, 1)]);
+
+        assert_eq!(graph.topological_order().as_slice(), [0, 1]);
+    }
+
+    #[test]
+    fn leaves_cycles_out() {
+        // 1 and 2 need each other, and 3 needs 2
+        let graph = graph(&[0, 1, 2, 3], &[(1, 2), (2, 1), (2, 3)]);
+        let order = graph.topological_order();
+
+        assert_eq!(order.as_slice(), [0]);
+        assert_eq!(graph.cycle_behind(0, &order), 0);
+        assert_eq!(graph.cycle_behind(1, &order), 0b110);
+        assert_eq!(graph.cycle_behind(3, &order), 0b110);
+    }
+
+    #[test]
+    fn finds_self_dependencies() {
+        let graph = graph(&[0], &[(0, 0)]);
+        let order = graph.topological_order();
+
+        assert!(order.as_slice().is_empty());
+        assert_eq!(graph.cycle_behind(0, &order), 0b1);
+    }
+
+    #[test]
+    fn lists_dependents() {
+        let graph = graph(&[0, 1, 2], &[(0, 1), (0, 2)]);
+
+        assert!(graph.dependents(0).eq([1, 2]));
+        assert_eq!(graph.dependents(1).count(), 0);
+    }
+}
@@ -0,0 +1,308 @@
+use core::{ffi::CStr, time::Duration};
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    dir::Dir,
+    errno::Errno,
+    fcntl::{open, openat, AtFlags, OFlag},
+    libc::{c_int, dev_t, readlink, S_IFDIR, S_IFMT},
+    mount::{mount, umount, MsFlags},
+    sys::{
+        stat::{fstatat, stat, Mode},
+        statfs::{statfs, FsType, TMPFS_MAGIC},
+    },
+    unistd::{chdir, chroot, mkdir, unlinkat, UnlinkatFlags},
+};
+
+use crate::{
+    cmdline::KernelCommandLine,
+    exec::execv,
+    fixed::FixedCStr,
+    mount::{
+        early::mount_early_filesystems, mount_as_any_type,
+        options::MountOptions, resolve_source,
+    },
+    utils::{monotonic_time, sleep_for, NixPathExt},
+    Error,
+};
+
+/// The filesystem type of the initramfs on older kernels, or when
+/// `rootfstype=ramfs` is given
+const RAMFS_MAGIC: FsType = FsType(0x8584_58f6);
+
+/// Where the real root is mounted before being switched to
+static NEW_ROOT: &CStr = cstr!("/sysroot");
+
+/// The mounts carried over to the real root
+const API_MOUNTS: [&CStr; 4] =
+    [cstr!("/proc"), cstr!("/sys"), cstr!("/dev"), cstr!("/run")];
+
+/// Run when there's no `init=`: this very binary, which is still
+/// reachable through `/proc` once the initramfs is gone
+static SELF: &CStr = cstr!("/proc/self/exe");
+
+/// How long to wait for the root device to show up
+const ROOT_WAIT: Duration = Duration::from_secs(10);
+
+/// Where the kernel runs the init of an initramfs from
+static INITRAMFS_INIT: &CStr = cstr!("/init");
+
+/// Whether we're running from an initramfs, whose root filesystem
+/// is a ramfs or a tmpfs with an `/init`.
+///
+/// A system whose real root is a tmpfs, e.g. a stateless one, has
+/// no `/init` and so is not mistaken for an initramfs.
+pub fn running_from_initramfs() -> bool {
+    let is_ram_backed = statfs(cstr!("/")).is_ok_and(|status| {
+        let filesystem_type = status.filesystem_type();
+        filesystem_type == RAMFS_MAGIC
+            || filesystem_type == TMPFS_MAGIC
+    });
+
+    is_ram_backed && INITRAMFS_INIT.exists()
+}
+
+/// Mounts the real root filesystem described in the kernel command
+/// line, moves the pseudo filesystems onto it, deletes the contents
+/// of the initramfs and runs the real init from the real root.
+///
+/// Only returns if something went wrong.
+pub fn switch_root() -> crate::Result<()> {
+    // /proc is needed to read the command line
+    mount_early_filesystems()?;
+
+    let cmdline = KernelCommandLine::read()?;
+
+    mount_new_root(&cmdline)?;
+
+    // Checked before anything is moved or deleted, so that a missing
+    // init leaves the initramfs as it was, emergency shell included,
+    // and the switch can be tried again
+    let init = match find_init(&cmdline) {
+        Ok(init) => init,
+        Err(err) => {
+            let _ = umount(NEW_ROOT);
+            return Err(err);
+        }
+    };
+
+    for path in API_MOUNTS {
+        let mut target =
+            FixedCStr::<64>::from_bytes(NEW_ROOT.to_bytes())?;
+        target.push(path.to_bytes())?;
+
+        if let Err(errno) = mount(
+            Some(path),
+            target.as_cstr(),
+            None as Option<&str>,
+            MsFlags::MS_MOVE,
+            None as Option<&str>,
+        ) {
+            libc_eprintln!("Failed to move {:?}: {}", path, errno);
+        }
+    }
+
+    // The initramfs would otherwise take up memory forever
+    let root_device = stat(cstr!("/"))?.st_dev;
+    let root = open(
+        cstr!("/"),
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    remove_directory_contents(root, root_device);
+
+    chdir(NEW_ROOT)?;
+    mount(
+        Some(cstr!(".")),
+        cstr!("/"),
+        None as Option<&str>,
+        MsFlags::MS_MOVE,
+        None as Option<&str>,
+    )?;
+    chroot(cstr!("."))?;
+    chdir(cstr!("/"))?;
+
+    execv(init.as_cstr())
+}
+
+/// The init to run from the real root: `init=` or, without it,
+/// [`SELF`]
+fn find_init(
+    cmdline: &KernelCommandLine,
+) -> crate::Result<FixedCStr<256>> {
+    let Some(path) = cmdline.value(b"init") else {
+        return FixedCStr::from_bytes(SELF.to_bytes());
+    };
+
+    let init = FixedCStr::from_bytes(path)?;
+    if !is_executable_in_new_root(path)? {
+        libc_eprintln!(
+            "{:?} is not executable on the real root",
+            init.as_cstr()
+        );
+        return Err(Errno::ENOEXEC.into());
+    }
+
+    Ok(init)
+}
+
+/// Whether `path` is executable from the real root, following
+/// symbolic links the way they will be once [`NEW_ROOT`] is `/`,
+/// e.g. `/sbin/init` pointing to `/usr/bin/incipio`.
+fn is_executable_in_new_root(path: &[u8]) -> crate::Result<bool> {
+    // Same as the kernel's limit
+    const MAX_SYMLINKS: usize = 40;
+
+    let mut path = FixedCStr::<256>::from_bytes(path)?;
+
+    for _ in 0..MAX_SYMLINKS {
+        let mut new_path =
+            FixedCStr::<256>::from_bytes(NEW_ROOT.to_bytes())?;
+        new_path.push(path.as_bytes())?;
+
+        let mut target = [0; 256];
+        // Safety: `readlink` writes at most `target.len()` bytes
+        let len = unsafe {
+            readlink(
+                new_path.as_cstr().as_ptr(),
+                target.as_mut_ptr().cast(),
+                target.len(),
+            )
+        };
+
+        // Not a symbolic link, or not there at all
+        let Ok(len) = usize::try_from(len) else {
+            return Ok(new_path.as_cstr().is_executable());
+        };
+        let target = &target[..len];
+
+        if target.starts_with(b"/") {
+            path = FixedCStr::from_bytes(target)?;
+        } else {
+            // Relative to the directory the link is in
+            let directory_len = path
+                .as_bytes()
+                .iter()
+                .rposition(|&byte| byte == b'/')
+                .map_or(0, |idx| idx + 1);
+            let mut relative = FixedCStr::<256>::from_bytes(
+                &path.as_bytes()[..directory_len],
+            )?;
+            relative.push(target)?;
+            path = relative;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Mounts the root filesystem given by `root=`, `rootfstype=` and
+/// `rootflags=` onto [`NEW_ROOT`], read-only unless `rw` is given
+fn mount_new_root(cmdline: &KernelCommandLine) -> crate::Result<()> {
+    let root = cmdline.value(b"root").ok_or(Error::MissingRoot)?;
+
+    let mut options = MountOptions::parse(
+        cmdline.value(b"rootflags").unwrap_or(b""),
+    )?;
+    if !cmdline.has_flag(b"rw") {
+        options.flags |= MsFlags::MS_RDONLY;
+    }
+
+    let filesystem_type = cmdline
+        .value(b"rootfstype")
+        .map(FixedCStr::<32>::from_bytes)
+        .transpose()?;
+
+    // The device may take a while to be probed, e.g. USB disks
+    let deadline = monotonic_time() + ROOT_WAIT;
+    let source = loop {
+        match resolve_source(root) {
+            Ok(source) if source.as_cstr().exists() => break source,
+            Ok(_) | Err(Error::DeviceNotFound) => {}
+            Err(err) => return Err(err),
+        }
+
+        if monotonic_time() >= deadline {
+            libc_eprintln!(
+                "Root device {:?} not found",
+                core::str::from_utf8(root).unwrap_or("")
+            );
+            return Err(Error::DeviceNotFound);
+        }
+        sleep_for(Duration::from_millis(100));
+    };
+
+    match mkdir(NEW_ROOT, Mode::S_IRWXU) {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(errno) => return Err(errno.into()),
+    }
+
+    let mount_as = |filesystem_type: &CStr| {
+        mount(
+            Some(source.as_cstr()),
+            NEW_ROOT,
+            Some(filesystem_type),
+            options.flags,
+            Some(options.data.as_cstr()),
+        )
+    };
+
+    match filesystem_type {
+        Some(filesystem_type) => mount_as(filesystem_type.as_cstr())?,
+        None => mount_as_any_type(mount_as)?,
+    }
+
+    Ok(())
+}
+
+/// Deletes everything in the directory `fd` that is on the
+/// filesystem `device`, leaving alone other filesystems mounted
+/// inside of it. Takes ownership of `fd`.
+///
+/// Deleting is best-effort: whatever can't be deleted is left
+/// behind.
+fn remove_directory_contents(fd: c_int, device: dev_t) {
+    let mut directory = match Dir::from_fd(fd) {
+        Ok(directory) => directory,
+        Err(_) => return,
+    };
+
+    for entry in directory.iter().flatten() {
+        let name = entry.file_name();
+        if name == cstr!(".") || name == cstr!("..") {
+            continue;
+        }
+
+        let Ok(status) =
+            fstatat(fd, name, AtFlags::AT_SYMLINK_NOFOLLOW)
+        else {
+            continue;
+        };
+
+        // e.g. the new root
+        if status.st_dev != device {
+            continue;
+        }
+
+        let is_directory = status.st_mode & S_IFMT == S_IFDIR;
+        let flags = if is_directory {
+            if let Ok(child) = openat(
+                fd,
+                name,
+                OFlag::O_RDONLY
+                    | OFlag::O_DIRECTORY
+                    | OFlag::O_NOFOLLOW
+                    | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            ) {
+                remove_directory_contents(child, device);
+            }
+            UnlinkatFlags::RemoveDir
+        } else {
+            UnlinkatFlags::NoRemoveDir
+        };
+
+        let _ = unlinkat(Some(fd), name, flags);
+    }
+}
@@ -0,0 +1,24 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// How much incipio tells about what it's doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// Only errors
+    Quiet,
+    /// Errors and progress, such as the steps of shutting down
+    Normal,
+    /// Everything, including what each process and mount is up to
+    Debug,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of `level` should be logged
+pub fn is_enabled(level: LogLevel) -> bool {
+    LOG_LEVEL.load(Ordering::Relaxed) >= level as u8
+}
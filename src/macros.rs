@@ -8,19 +8,36 @@
 /// ```
 /// generates
 /// ```
-/// fork_and_execute_command([cstr!("ls").as_ptr(), cstr!("-li").as_ptr(), core::ptr::null()])
+/// let mut command = Command::new(b"ls")?;
+/// command.arg(b"-li")?;
+/// command.status()?
 /// ```
 macro_rules! run {
-    ($x:expr, $($y:expr),+) => (
-        $crate::exec::fork_and_execute_command([cstr::cstr!($x).as_ptr(), $(cstr::cstr!($y).as_ptr(),)* core::ptr::null()], true)?
-    )
+    ($x:expr, $($y:expr),+) => ({
+        let mut command =
+            $crate::exec::command::Command::new($x.as_bytes())?;
+        $(command.arg($y.as_bytes())?;)+
+        command.status()?
+    })
 }
 
-/// Forks the current process, runs the given command in the child
-/// process without waiting for the child process to exit.
+/// Logs progress messages, unless `quiet` was given to the kernel
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::is_enabled($crate::log::LogLevel::Normal) {
+            libc_print::libc_eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Logs detailed messages, only if `incipio.debug` was given to the
+/// kernel
 #[macro_export]
-macro_rules! run_in_background {
-    ($x:expr, $($y:expr),+) => (
-        $crate::exec::fork_and_execute_command([cstr::cstr!($x).as_ptr(), $(cstr::cstr!($y).as_ptr(),)* core::ptr::null()], false)?
-    )
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::is_enabled($crate::log::LogLevel::Debug) {
+            libc_print::libc_eprintln!($($arg)*);
+        }
+    };
 }
@@ -1,24 +1,61 @@
-#![no_std]
-#![no_main]
+// The test harness needs std and provides its own `main`
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 /// Utilities related to booting the system up and down
 pub mod boot;
+/// Parsing of the kernel command line
+pub mod cmdline;
+/// incipio's own configuration file
+pub mod config;
+/// The control socket through which `incipioctl` talks to us
+pub mod control;
+/// The shell started when booting fails
+pub mod emergency;
 /// Crate's error enum and Result alias
 mod error;
+/// The main loop incipio runs after booting the system
+pub mod event_loop;
 /// Utilities related to executing files
 pub mod exec;
+/// Fixed-capacity containers, so that we don't need the heap
+pub mod fixed;
 /// Utilities related to files and filesystems
 pub mod fs;
+/// Filesystem checks at boot
+pub mod fsck;
+/// Ordering of services according to their dependencies
+pub mod graph;
+/// Switching from an initramfs to the real root filesystem
+pub mod initramfs;
+/// How much incipio logs
+pub mod log;
 /// Macros to help in the code
 pub mod macros;
 /// Utilities related to (un)mounting filesystems
 pub mod mount;
+/// Parsing of incipio's configuration files
+pub mod parser;
+/// Utilities related to looking up users in `/etc/passwd`
+pub mod passwd;
 /// Utilities related to reading or setting PID values
 pub mod pid;
+/// The `poweroff`, `reboot`, `halt` and `shutdown` commands
+pub mod power;
 /// A seed for rand generated at compile-time in build.rs
 mod rand_seed;
+/// Single-user mode, for repairs from the console
+pub mod rescue;
+/// A random seed kept across reboots
+pub mod seed;
+/// Definitions of the services incipio starts
+pub mod service;
 /// Utilities related to handling signal interrupts
 pub mod signal;
+/// Keeps supervised processes running, respawning them when they exit
+pub mod supervisor;
+/// Sets of services to run, akin to runlevels
+pub mod target;
 /// Utilities related to starting TTYs
 pub mod tty;
 /// General utilities
@@ -26,43 +63,154 @@ pub mod utils;
 /// Utilities related to waiting for processes to exit
 pub mod wait;
 
+use core::ffi::c_char;
+
 use boot::boot_up_system;
+use cmdline::{BootMode, BootOptions};
+use config::Config;
+use control::ControlServer;
+use emergency::emergency_shell;
 pub use error::{Error, Result};
+use event_loop::run_event_loop;
+use initramfs::{running_from_initramfs, switch_root};
 pub use libc_print::libc_eprintln as eprintln;
-use mount::mount_filesystem;
+use log::set_log_level;
+use mount::{
+    early::mount_early_filesystems, mount_fstab_filesystems,
+    mount_root_filesystem,
+};
 use nix::libc::EXIT_FAILURE;
 use pid::ensure_running_as_init_system;
-use signal::install_signal_handler;
+use power::PowerCommand;
+use rescue::rescue_shell;
+use service::start_services;
+use signal::block_handled_signals;
+use supervisor::Supervisor;
+use utils::program_arguments;
+
+/// Boots the system and then supervises it until it's shut down.
+///
+/// Exiting would make the kernel panic, so each step that fails is
+/// handed to the administrator through [`retry`]. Only the steps
+/// before anything is started are retried, so that nothing is ever
+/// started twice.
+fn run() -> ! {
+    // When run as the init of an initramfs, mount the real root and
+    // run the real init from it. Checked on every try, since a switch
+    // that failed after deleting the initramfs can't be tried again.
+    retry(|| {
+        if !running_from_initramfs() {
+            return Ok(());
+        }
+
+        match switch_root() {
+            // Without root= there's nothing to switch to, so the
+            // ramfs or tmpfs we're on is the real root
+            Ok(()) | Err(Error::MissingRoot) => Ok(()),
+            Err(err) => {
+                eprintln!("Failed to switch to the real root");
+                Err(err)
+            }
+        }
+    });
+
+    // Read /etc/incipio/incipio.conf, if any
+    let config = Config::load();
 
-fn run() -> Result<()> {
-    // Make sure we're running with PID 1.
-    ensure_running_as_init_system()?;
+    // From now on, the signals we handle are only received through
+    // this signalfd
+    let signals = retry(|| {
+        block_handled_signals(config.signal_profile)
+            .map_err(Error::from)
+    });
 
-    // Get our signal handler up and running
-    install_signal_handler()?;
+    // Mount procfs, sysfs, /run, /dev, /dev/pts, /dev/shm and
+    // /run/lock, or whatever /etc/incipio/early-mounts lists instead
+    retry(mount_early_filesystems);
+
+    // Now that /proc is there, see what the kernel was booted with
+    let boot_options = BootOptions::read();
+    set_log_level(boot_options.log_level);
+
+    if boot_options.mode == BootMode::Emergency {
+        rescue_shell("Emergency mode requested", &config);
+    }
+
+    // Check root and remount it, usually read-write
+    mount_root_filesystem(&boot_options);
+
+    // Like sysvinit's runlevel S, before any getty or service
+    if boot_options.mode == BootMode::Single {
+        rescue_shell("Single-user mode requested", &config);
+    }
 
-    // Mount procfs, sysfs, /run and /dev, /dev/pts, /dev/shm
-    mount_filesystem()?;
+    // Mount everything else in /etc/fstab and turn on swap
+    retry(mount_fstab_filesystems);
 
-    // Set hostname, seed /dev/urandom and disable Ctrl+Alt+Del
-    boot_up_system()?;
+    // Keeps the gettys (and later on, services) running
+    let mut supervisor = Supervisor::new(config.default_target);
 
-    Ok(())
+    // Set hostname, seed /dev/urandom, disable Ctrl+Alt+Del and
+    // open TTYs
+    boot_up_system(&mut supervisor, &config, &boot_options);
+
+    // Start the services defined in /etc/incipio/services
+    start_services(&mut supervisor);
+
+    // Listen for requests from incipioctl. Not being able to do so
+    // is unfortunate, but no reason to give up on the boot.
+    let control = ControlServer::bind()
+        .map_err(|err| {
+            eprintln!(
+                "Failed to create control socket: {}",
+                err.description()
+            )
+        })
+        .ok();
+
+    // Handle signals and requests until the system is shut down
+    run_event_loop(&signals, &mut supervisor, control, &config)
 }
 
-#[no_mangle]
+/// Runs `step` until it succeeds, starting an emergency shell after
+/// each failure. `step` must be safe to run again after failing.
+fn retry<T>(mut step: impl FnMut() -> Result<T>) -> T {
+    loop {
+        match step() {
+            Ok(value) => return value,
+            Err(error) => {
+                eprintln!("Error: {}", error.description());
+                emergency_shell("Booting failed");
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(test), no_mangle)]
+// `argv` comes straight from the C runtime
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn main(
-    _argc: isize,
-    _argv: *const *const u8,
+    argc: isize,
+    argv: *const *const c_char,
 ) -> isize {
-    match run() {
-        Ok(()) => {
-            // Should not be reached, kernel will panic
-        }
-        Err(error) => {
-            eprintln!("Error: {}", error.description());
+    // Safety: the C runtime hands us `argc` valid strings
+    let mut arguments = unsafe { program_arguments(argc, argv) };
+
+    // When run through a symlink called e.g. `reboot`, act as that
+    // command instead of as the init system
+    if let Some(program) = arguments.next() {
+        if let Some(command) =
+            PowerCommand::from_program_name(program)
+        {
+            return command.run(program, arguments);
         }
     }
 
-    EXIT_FAILURE as isize
+    // Only PID 1 can boot the system
+    if let Err(error) = ensure_running_as_init_system() {
+        eprintln!("Error: {}", error.description());
+        return EXIT_FAILURE as isize;
+    }
+
+    run()
 }
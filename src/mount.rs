@@ -3,37 +3,59 @@ use core::ffi::CStr;
 use cstr::cstr;
 use libc_print::libc_eprintln;
 use nix::{
-    mount::{mount, umount, MsFlags},
-    sys::stat::Mode,
-    unistd::mkdir,
+    errno::Errno,
+    mount::{mount, umount, umount2, MntFlags, MsFlags},
+    sys::wait::WaitStatus,
 };
 
-use crate::{fs::MountPointParser, run};
+use self::{
+    device::{find_device, DevicePath, Tag},
+    options::MountOptions,
+};
+use crate::{
+    cmdline::BootOptions,
+    exec::command::Command,
+    fixed::FixedCStr,
+    fs::{is_mount_point, Entry, MountPointParser},
+    fsck::check_filesystem,
+    run,
+    utils::{read_file, NixPathExt},
+    Error,
+};
 
-/// 755 means read and execute access for everyone and also write
-/// access for the owner of the file.
-macro_rules! perms_0755 {
-    () => {
-        // Work-around since bit-or for Mode is not const
-        Mode::S_IRWXU | Mode::S_IXOTH | Mode::S_IROTH
-    };
-}
+/// Finding block devices by UUID or label, without udev
+pub mod device;
+/// Pseudo filesystems mounted before anything else
+pub mod early;
+/// Translation of fstab mount options into `mount(2)` arguments
+pub mod options;
 
 static ROOT: &CStr = cstr!("/");
-static MODE_0755: &CStr = cstr!("mode=0755");
-
-pub fn mount_filesystem() -> crate::Result<()> {
-    // Mount procfs, sysfs, /run and /dev
-    mount_pseudo_filesystems()?;
+static FSTAB: &CStr = cstr!("/etc/fstab");
+/// Followed by the filesystem type, the helper `mount(8)` would run
+/// to mount it
+static MOUNT_HELPER: &[u8] = b"/sbin/mount.";
 
-    // Mount /dev/shm, /dev/pts/, /run/lock
-    remaining_filesystem_runlevel()?;
+/// Prepares the root filesystem, which is all single-user mode
+/// gets before its shell runs.
+pub fn mount_root_filesystem(boot_options: &BootOptions) {
+    // Check root while it's still read-only
+    check_root_filesystem();
 
-    // Remount root
-    run!("/usr/bin/mount", "remount,rw", "/");
+    // Remount root with its options in /etc/fstab, which usually
+    // makes it writable
+    if let Err(err) = remount_root(boot_options.root_read_only) {
+        libc_eprintln!(
+            "Failed to remount root: {}",
+            err.description()
+        );
+    }
+}
 
+/// Mounts and turns on everything in [`FSTAB`] but root
+pub fn mount_fstab_filesystems() -> crate::Result<()> {
     // Mount all filesystems
-    run!("/usr/bin/mount", "-a");
+    mount_fstab_entries()?;
 
     // Turn on all swap partitions in /etc/fstab
     // Runs `swapon -a`
@@ -42,109 +64,467 @@ pub fn mount_filesystem() -> crate::Result<()> {
     Ok(())
 }
 
-pub fn turn_off_swap_partitions() -> crate::Result<()> {
-    run!("/usr/bin/swapoff", "-a");
+/// Checks the root filesystem if its entry in [`FSTAB`] asks for it
+fn check_root_filesystem() {
+    let Ok(mut parser) = MountPointParser::new(FSTAB) else {
+        return;
+    };
+
+    if let Some(entry) = parser.find(|entry| entry.path() == ROOT) {
+        check_filesystem(&entry);
+    }
+}
+
+/// Remounts the root filesystem with the options it's given in
+/// [`FSTAB`].
+///
+/// Without an entry there, `read_only` decides, and root is made
+/// writable if it's not set. It comes from `ro` or `rw` in the
+/// kernel command line, which most bootloaders pass to have root
+/// checked before it's written to, not to keep it read-only.
+fn remount_root(read_only: Option<bool>) -> crate::Result<()> {
+    let mut options = MountOptions::parse(b"rw")?;
+    if let Some(read_only) = read_only {
+        options.flags.set(MsFlags::MS_RDONLY, read_only);
+    }
+
+    match MountPointParser::new(FSTAB) {
+        Ok(mut parser) => {
+            if let Some(entry) =
+                parser.find(|entry| entry.path() == ROOT)
+            {
+                options =
+                    MountOptions::parse(entry.options().to_bytes())?;
+            }
+        }
+        Err(Error::Errno(Errno::ENOENT)) => {}
+        Err(err) => return Err(err),
+    }
+
+    mount(
+        None as Option<&str>,
+        ROOT,
+        None as Option<&str>,
+        MsFlags::MS_REMOUNT | options.flags,
+        Some(options.data.as_cstr()),
+    )?;
+
     Ok(())
 }
 
-/// Read through the entries of `/proc/mounts` attempting to unmount
-/// the file systems found.
+/// Mounts every entry of [`FSTAB`] not marked `noauto`, like
+/// `fsck -A` followed by `mount -a` would.
 ///
-/// If an unmount operation fails, we'll try to remount the given FS
-/// as read-only
-pub fn unmount_all_filesystems() -> crate::Result<()> {
-    let parser = MountPointParser::new(cstr!("/proc/mounts"))?;
-    let is_root = |path: &CStr| path == ROOT;
+/// Entries are checked in the order of their pass numbers, and then
+/// mounted in the order they appear in, which must have parents
+/// before the filesystems mounted inside of them. Every entry is
+/// attempted, and the first failure of an entry without the
+/// `nofail` option is returned.
+fn mount_fstab_entries() -> crate::Result<()> {
+    let parser = match MountPointParser::new(FSTAB) {
+        Ok(parser) => parser,
+        Err(Error::Errno(Errno::ENOENT)) => return Ok(()),
+        Err(err) => return Err(err),
+    };
 
-    for entry in parser {
-        let path = entry.path();
+    check_fstab_filesystems()?;
 
-        // We'll try to remount this file system as read-only
-        // if unmounting it fails or if it's the root partition
-        let mut should_remount = is_root(path);
+    let mut first_error = None;
 
-        if let Err(err) = umount(path) {
-            libc_eprintln!("Failed to unmount {:?}: {}", path, err);
-            should_remount = true;
+    for entry in parser {
+        if let Err(err) = mount_fstab_entry(&entry) {
+            libc_eprintln!(
+                "Failed to mount {:?}: {}",
+                entry.path(),
+                err.description()
+            );
+            first_error.get_or_insert(err);
         }
+    }
 
-        if should_remount {
-            if let Err(err) = mount(
-                None as Option<&str>,
-                path,
-                None as Option<&str>,
-                MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
-                None as Option<&str>,
-            ) {
-                libc_eprintln!(
-                    "Failed to remount {:?} as read-only: {}",
-                    path,
-                    err
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Checks the entries of [`FSTAB`] that are mounted at boot, all of
+/// pass 1 first, then all of pass 2 and so on
+fn check_fstab_filesystems() -> crate::Result<()> {
+    let mut pass = 1;
+
+    loop {
+        let mut next_pass = None;
+
+        for entry in MountPointParser::new(FSTAB)? {
+            let entry_pass = entry.pass_number();
+            if entry_pass > pass {
+                next_pass = Some(
+                    next_pass.map_or(entry_pass, |next: i32| {
+                        next.min(entry_pass)
+                    }),
                 );
             }
+            if entry_pass != pass {
+                continue;
+            }
+
+            // Bad options are reported when the entry is mounted
+            let Ok(options) =
+                MountOptions::parse(entry.options().to_bytes())
+            else {
+                continue;
+            };
+
+            // Bind mounts have nothing of their own to check
+            if is_mounted_at_boot(&entry, &options)
+                && !options.flags.contains(MsFlags::MS_BIND)
+            {
+                check_filesystem(&entry);
+            }
         }
+
+        match next_pass {
+            Some(next) => pass = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Whether `entry` is one [`mount_fstab_entries`] mounts. Root is
+/// remounted separately, and swap is not mounted at all.
+fn is_mounted_at_boot(entry: &Entry, options: &MountOptions) -> bool {
+    !options.noauto
+        && entry.path() != ROOT
+        && entry.filesystem_type().to_bytes() != b"swap"
+}
+
+/// Mounts `entry`, unless it's not meant to be mounted at boot or
+/// is mounted already
+fn mount_fstab_entry(entry: &Entry) -> crate::Result<()> {
+    let options = MountOptions::parse(entry.options().to_bytes())?;
+
+    if !is_mounted_at_boot(entry, &options) {
+        return Ok(());
+    }
+
+    // Mounted by an earlier attempt, or by the initramfs
+    if let Ok(true) = is_mount_point(entry.path()) {
+        return Ok(());
+    }
+
+    match mount_entry(entry, &options) {
+        Ok(()) => {
+            crate::debug!("Mounted {:?}", entry.path());
+            Ok(())
+        }
+        Err(err) if options.nofail => {
+            libc_eprintln!(
+                "Failed to mount {:?}: {}",
+                entry.path(),
+                err.description()
+            );
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn mount_entry(
+    entry: &Entry,
+    options: &MountOptions,
+) -> crate::Result<()> {
+    let filesystem_type = entry.filesystem_type();
+
+    // Like mount(8), leave the types that have a helper to it
+    let mut helper = FixedCStr::<64>::from_bytes(MOUNT_HELPER)?;
+    helper.push(filesystem_type.to_bytes())?;
+    if helper.as_cstr().is_executable() {
+        return run_mount_helper(helper.as_bytes(), entry);
+    }
+    if needs_mount_helper(filesystem_type.to_bytes()) {
+        return Err(Error::MissingMountHelper);
+    }
+
+    let source = resolve_source(entry.fsname().to_bytes())?;
+    let mount_as = |filesystem_type: &CStr| {
+        mount(
+            Some(source.as_cstr()),
+            entry.path(),
+            Some(filesystem_type),
+            options.flags,
+            Some(options.data.as_cstr()),
+        )
+    };
+
+    if filesystem_type.to_bytes() == b"auto" {
+        mount_as_any_type(mount_as)?;
+    } else {
+        mount_as(filesystem_type)?;
     }
 
     Ok(())
 }
 
-/// Remaining FS run-level operations to take place after
-/// pseudo-filesystems have been mounted.
-// TODO: make these paths configurable for those who might want to
-// mount /run/shm instead of /dev/shm, /var/lock instead of /run/lock,
-// and so on
-fn remaining_filesystem_runlevel() -> nix::Result<()> {
-    fn mkdir_0755(path: &CStr) -> nix::Result<()> {
-        mkdir(path, perms_0755!())
+/// Runs e.g. `/sbin/mount.nfs server:/export /mnt -o ro`, passing
+/// on the options as they are written in [`FSTAB`]
+fn run_mount_helper(
+    helper: &[u8],
+    entry: &Entry,
+) -> crate::Result<()> {
+    let mut command = Command::new(helper)?;
+    command
+        .arg(entry.fsname().to_bytes())?
+        .arg(entry.path().to_bytes())?
+        .arg(b"-o")?
+        .arg(entry.options().to_bytes())?;
+
+    match command.status()? {
+        WaitStatus::Exited(_, 0) => Ok(()),
+        _ => Err(Error::MountHelperFailed),
+    }
+}
+
+/// Whether the kernel can't mount `filesystem_type` without the
+/// help of a userspace program, as is the case of network and FUSE
+/// filesystems
+fn needs_mount_helper(filesystem_type: &[u8]) -> bool {
+    matches!(
+        filesystem_type,
+        b"nfs" | b"nfs4" | b"cifs" | b"smb3" | b"fuse" | b"fuseblk"
+    ) || filesystem_type.starts_with(b"fuse.")
+}
+
+/// Turns the `UUID=`, `LABEL=`, `PARTUUID=` and `PARTLABEL=` tags
+/// fstab allows into the path of the device they refer to.
+pub fn resolve_source(fsname: &[u8]) -> crate::Result<DevicePath> {
+    match Tag::parse(fsname) {
+        Some((tag, value)) => find_device(tag, value),
+        None => DevicePath::from_bytes(fsname),
     }
+}
 
-    // Mounts pseudo-terminals through /dev/pts.
-    // Assumes that `/dev/` has already been mounted.
-    mkdir_0755(cstr!("/dev/pts"))?;
+/// Tries `mount_as` with every filesystem type in
+/// `/proc/filesystems` that is backed by a block device, until one
+/// of them is not rejected as the wrong type.
+pub fn mount_as_any_type(
+    mount_as: impl Fn(&CStr) -> nix::Result<()>,
+) -> nix::Result<()> {
+    let mut filesystems = [0; 2048];
+    let len =
+        read_file(cstr!("/proc/filesystems"), &mut filesystems)?;
 
-    // Mounts the temporary shared memory storage
-    mkdir_0755(cstr!("/dev/shm"))?;
+    // Lines look like `nodev\tproc` or `\text4`
+    for line in filesystems[..len].split(|&byte| byte == b'\n') {
+        let (flags, name) =
+            match line.iter().position(|&b| b == b'\t') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => continue,
+            };
 
-    // Mounts the directory for lock files, i.e. files indicating that
-    // a shared device or other system resource is in use and
-    // containing the identity of the process (PID) using it.
-    mkdir_0755(cstr!("/run/lock"))
+        if flags == b"nodev" {
+            continue;
+        }
+
+        let Ok(name) = FixedCStr::<32>::from_bytes(name) else {
+            continue;
+        };
+
+        match mount_as(name.as_cstr()) {
+            Err(Errno::EINVAL) => continue,
+            result => return result,
+        }
+    }
+
+    Err(Errno::EINVAL)
 }
 
-fn mount_pseudo_filesystems() -> nix::Result<()> {
-    // Mounting procfs
-    mount(
-        Some(cstr!("proc")),
-        cstr!("/proc"),
-        Some(cstr!("proc")),
-        MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-        None as Option<&str>,
-    )?;
+pub fn turn_off_swap_partitions() -> crate::Result<()> {
+    run!("/usr/bin/swapoff", "-a");
+    Ok(())
+}
 
-    // Mounting sys
-    mount(
-        Some(cstr!("sys")),
-        cstr!("/sys"),
-        Some(cstr!("sysfs")),
-        MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-        None as Option<&str>,
-    )?;
+/// The most mounts [`unmount_all_filesystems`] keeps track of at
+/// once
+const MAX_MOUNTS: usize = 64;
 
-    mount(
-        Some(cstr!("run")),
-        cstr!("/run"),
-        Some(cstr!("tmpfs")),
-        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-        Some(MODE_0755),
-    )?;
+/// How many times unmounting busy filesystems is attempted before
+/// giving up on them
+const MAX_UNMOUNT_PASSES: usize = 4;
 
-    mount(
-        Some(cstr!("dev")),
-        cstr!("/dev"),
-        Some(cstr!("devtmpfs")),
-        MsFlags::MS_NOSUID,
-        Some(MODE_0755),
-    )?;
+/// Filesystem types that hold no data of their own, which are left
+/// mounted on shutdown
+const PSEUDO_FILESYSTEM_TYPES: [&[u8]; 19] = [
+    b"rootfs",
+    b"proc",
+    b"sysfs",
+    b"devtmpfs",
+    b"devpts",
+    b"securityfs",
+    b"cgroup",
+    b"cgroup2",
+    b"pstore",
+    b"efivarfs",
+    b"bpf",
+    b"debugfs",
+    b"tracefs",
+    b"configfs",
+    b"fusectl",
+    b"mqueue",
+    b"hugetlbfs",
+    b"binfmt_misc",
+    b"autofs",
+];
+
+/// Mount points of kernel interfaces and runtime state, which are
+/// left mounted on shutdown. Filesystems mounted below them, e.g. a
+/// USB stick in `/run/media`, are unmounted like any other.
+const API_MOUNT_POINTS: [&[u8]; 4] =
+    [b"/proc", b"/sys", b"/dev", b"/run"];
+
+type MountTarget = FixedCStr<256>;
+
+/// Unmounts every filesystem in `/proc/mounts`, except for pseudo
+/// filesystems and root, which is remounted read-only instead.
+///
+/// Mounts are unmounted in the reverse order they were mounted in,
+/// so that nested mounts go before their parents. The ones that are
+/// busy are retried for a few passes, since unmounting others may
+/// free them up, and are finally remounted read-only and detached.
+///
+/// Mounts are handled [`MAX_MOUNTS`] at a time, the deepest first,
+/// with `/proc/mounts` read again until the rest are gone too.
+pub fn unmount_all_filesystems() -> crate::Result<()> {
+    loop {
+        let mut targets = [None; MAX_MOUNTS];
+        let (len, left_out) = read_mount_targets(&mut targets)?;
+
+        let removed_any = unmount_targets(&mut targets[..len]);
+        if !left_out || !removed_any {
+            break;
+        }
+    }
+
+    // Only now that nothing is mounted on top of it
+    remount_read_only(ROOT);
 
     Ok(())
 }
+
+/// Fills `targets` with the mount points in `/proc/mounts` that
+/// [`should_unmount`] accepts, in the order they were mounted in.
+///
+/// When they don't all fit, the earliest ones are left out, since
+/// the others may be mounted inside of them. Returns how many were
+/// read and whether any was left out.
+fn read_mount_targets(
+    targets: &mut [Option<MountTarget>; MAX_MOUNTS],
+) -> crate::Result<(usize, bool)> {
+    let mut count = 0;
+
+    for entry in MountPointParser::new(cstr!("/proc/mounts"))? {
+        if !should_unmount(&entry) {
+            continue;
+        }
+
+        match MountTarget::from_bytes(entry.path().to_bytes()) {
+            Ok(target) => {
+                targets[count % MAX_MOUNTS] = Some(target);
+                count += 1;
+            }
+            Err(err) => {
+                libc_eprintln!(
+                    "Not unmounting {:?}: {}",
+                    entry.path(),
+                    err.description()
+                )
+            }
+        }
+    }
+
+    if count > MAX_MOUNTS {
+        // The earliest of the ones kept was overwritten last
+        targets.rotate_left(count % MAX_MOUNTS);
+        Ok((MAX_MOUNTS, true))
+    } else {
+        Ok((count, false))
+    }
+}
+
+/// Unmounts `targets`, detaching the ones that stay busy. Returns
+/// whether any of them was unmounted or detached.
+fn unmount_targets(targets: &mut [Option<MountTarget>]) -> bool {
+    let mut removed_any = false;
+
+    for _ in 0..MAX_UNMOUNT_PASSES {
+        let mut unmounted_any = false;
+
+        // Deepest mounts come last in /proc/mounts
+        for slot in targets.iter_mut().rev() {
+            let Some(target) = slot else {
+                continue;
+            };
+
+            match umount(target.as_cstr()) {
+                Ok(()) => {
+                    *slot = None;
+                    unmounted_any = true;
+                }
+                // It's not a mount point anymore, e.g. it was
+                // mounted over
+                Err(Errno::EINVAL | Errno::ENOENT) => *slot = None,
+                Err(_) => {}
+            }
+        }
+
+        if !unmounted_any {
+            break;
+        }
+        removed_any = true;
+    }
+
+    // Whatever is still busy is at least made safe to power off
+    for target in targets.iter().rev().flatten() {
+        let target = target.as_cstr();
+        libc_eprintln!(
+            "Failed to unmount {:?}, detaching it",
+            target
+        );
+
+        remount_read_only(target);
+        match umount2(target, MntFlags::MNT_DETACH) {
+            Ok(()) => removed_any = true,
+            Err(errno) => {
+                libc_eprintln!(
+                    "Failed to detach {:?}: {}",
+                    target,
+                    errno
+                )
+            }
+        }
+    }
+
+    removed_any
+}
+
+fn should_unmount(entry: &Entry) -> bool {
+    let path = entry.path().to_bytes();
+
+    path != ROOT.to_bytes()
+        && !API_MOUNT_POINTS.contains(&path)
+        && !PSEUDO_FILESYSTEM_TYPES
+            .contains(&entry.filesystem_type().to_bytes())
+}
+
+fn remount_read_only(path: &CStr) {
+    if let Err(err) = mount(
+        None as Option<&str>,
+        path,
+        None as Option<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None as Option<&str>,
+    ) {
+        libc_eprintln!(
+            "Failed to remount {:?} as read-only: {}",
+            path,
+            err
+        );
+    }
+}
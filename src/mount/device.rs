@@ -0,0 +1,566 @@
+use core::{ffi::CStr, fmt::Write};
+
+use cstr::cstr;
+use nix::{
+    dir::Dir,
+    fcntl::{open, OFlag},
+    sys::{stat::Mode, uio::pread},
+    unistd::close,
+};
+
+use crate::{
+    fixed::FixedCStr,
+    parser::parse_number,
+    utils::{read_file, NixPathExt},
+    Error,
+};
+
+/// Where the kernel lists every block device, partitions included
+static SYS_BLOCK: &CStr = cstr!("/sys/class/block");
+
+/// The path of a block device, e.g. `/dev/sda1`
+pub type DevicePath = FixedCStr<256>;
+
+/// A UUID formatted the way `blkid` prints it
+type Uuid = FixedCStr<40>;
+
+/// What identifies a block device in place of its path, as in
+/// `UUID=...` in `/etc/fstab` or `root=UUID=...`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    /// The UUID of the filesystem
+    Uuid,
+    /// The label of the filesystem
+    Label,
+    /// The UUID of the partition in its partition table
+    PartUuid,
+    /// The name of the partition in its (GPT) partition table
+    PartLabel,
+}
+
+impl Tag {
+    /// Splits e.g. `UUID=1234` into its tag and its value, if it's
+    /// tagged at all
+    pub fn parse(fsname: &[u8]) -> Option<(Self, &[u8])> {
+        const TAGS: [(&[u8], Tag); 4] = [
+            (b"UUID=", Tag::Uuid),
+            (b"LABEL=", Tag::Label),
+            (b"PARTUUID=", Tag::PartUuid),
+            (b"PARTLABEL=", Tag::PartLabel),
+        ];
+
+        TAGS.into_iter().find_map(|(prefix, tag)| {
+            fsname.strip_prefix(prefix).map(|value| (tag, value))
+        })
+    }
+
+    /// Where udev links the devices with this tag to, once it runs
+    fn udev_directory(self) -> &'static [u8] {
+        match self {
+            Tag::Uuid => b"/dev/disk/by-uuid/",
+            Tag::Label => b"/dev/disk/by-label/",
+            Tag::PartUuid => b"/dev/disk/by-partuuid/",
+            Tag::PartLabel => b"/dev/disk/by-partlabel/",
+        }
+    }
+}
+
+/// Finds the block device whose `tag` is `value`.
+///
+/// udev is usually not running yet when filesystems are mounted,
+/// so when its links don't exist the devices in [`SYS_BLOCK`] are
+/// probed instead, by reading their partition table or superblock.
+/// Only the filesystems most often mounted at boot are recognised:
+/// ext2/3/4, XFS, Btrfs and FAT.
+pub fn find_device(
+    tag: Tag,
+    value: &[u8],
+) -> crate::Result<DevicePath> {
+    let mut link = DevicePath::from_bytes(tag.udev_directory())?;
+    link.push(value)?;
+    if link.as_cstr().exists() {
+        return Ok(link);
+    }
+
+    let mut devices = Dir::open(
+        SYS_BLOCK,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    for entry in devices.iter() {
+        let entry = entry?;
+        let name = entry.file_name().to_bytes();
+        if name.starts_with(b".") {
+            continue;
+        }
+
+        // Devices that can't be probed, e.g. an empty CD drive, are
+        // not the one we're looking for
+        if let Ok(Some(device)) = probe(name, tag, value) {
+            return Ok(device);
+        }
+    }
+
+    Err(Error::DeviceNotFound)
+}
+
+/// Checks whether the device called `name` in [`SYS_BLOCK`] has
+/// `value` as its `tag`, returning its path if so
+fn probe(
+    name: &[u8],
+    tag: Tag,
+    value: &[u8],
+) -> crate::Result<Option<DevicePath>> {
+    // Skips empty loop devices and drives with no media in them
+    let mut size = [0; 32];
+    let len =
+        read_file(sys_path(name, b"size")?.as_cstr(), &mut size)?;
+    if parse_number(size[..len].trim_ascii()) == Some(0) {
+        return Ok(None);
+    }
+
+    let uevent = Uevent::read(name, b"uevent")?;
+    let mut device = DevicePath::from_bytes(b"/dev/")?;
+    device.push(
+        uevent.value(b"DEVNAME").ok_or(Error::DeviceNotFound)?,
+    )?;
+
+    let matches = match tag {
+        Tag::PartLabel => uevent.value(b"PARTNAME") == Some(value),
+        Tag::PartUuid => {
+            let Some(number) =
+                uevent.value(b"PARTN").and_then(parse_number)
+            else {
+                return Ok(None);
+            };
+
+            // The symlink in `SYS_BLOCK` leads to the partition's
+            // directory, which is inside the one of its disk
+            let disk = Uevent::read(name, b"../uevent")?;
+            let mut disk_device = DevicePath::from_bytes(b"/dev/")?;
+            disk_device.push(
+                disk.value(b"DEVNAME")
+                    .ok_or(Error::DeviceNotFound)?,
+            )?;
+
+            partition_uuid(disk_device.as_cstr(), number)?
+                .is_some_and(|uuid| {
+                    uuid.as_bytes().eq_ignore_ascii_case(value)
+                })
+        }
+        Tag::Uuid | Tag::Label => filesystem_ids(device.as_cstr())?
+            .is_some_and(|ids| match tag {
+                Tag::Uuid => {
+                    ids.uuid.as_bytes().eq_ignore_ascii_case(value)
+                }
+                _ => ids.label.as_bytes() == value,
+            }),
+    };
+
+    Ok(matches.then_some(device))
+}
+
+/// `/sys/class/block/<name>/<file>`
+fn sys_path(
+    name: &[u8],
+    file: &[u8],
+) -> crate::Result<FixedCStr<128>> {
+    let mut path = FixedCStr::from_bytes(SYS_BLOCK.to_bytes())?;
+    path.push(b"/")?;
+    path.push(name)?;
+    path.push(b"/")?;
+    path.push(file)?;
+
+    Ok(path)
+}
+
+/// The `KEY=value` lines of a `uevent` file in sysfs
+struct Uevent {
+    buffer: [u8; 512],
+    len: usize,
+}
+
+impl Uevent {
+    fn read(name: &[u8], file: &[u8]) -> crate::Result<Self> {
+        let mut uevent = Self {
+            buffer: [0; 512],
+            len: 0,
+        };
+        uevent.len = read_file(
+            sys_path(name, file)?.as_cstr(),
+            &mut uevent.buffer,
+        )?;
+
+        Ok(uevent)
+    }
+
+    fn value(&self, key: &[u8]) -> Option<&[u8]> {
+        self.buffer[..self.len]
+            .split(|&byte| byte == b'\n')
+            .find_map(|line| {
+                line.strip_prefix(key)?.strip_prefix(b"=")
+            })
+    }
+}
+
+/// What a filesystem is identified by
+struct FilesystemIds {
+    uuid: Uuid,
+    label: FixedCStr<256>,
+}
+
+/// Reads the UUID and label of the filesystem on `device` from its
+/// superblock, if it's of a type we recognise
+fn filesystem_ids(
+    device: &CStr,
+) -> crate::Result<Option<FilesystemIds>> {
+    // Where most superblocks are, and where Btrfs' is
+    let mut start = [0; 4096];
+    let mut btrfs = [0; 4096];
+
+    read_at(
+        device,
+        &mut [(0, &mut start), (0x10000, &mut btrfs)],
+    )?;
+
+    let mut ids = FilesystemIds {
+        uuid: Uuid::new(),
+        label: FixedCStr::new(),
+    };
+
+    let ext = &start[1024..2048];
+    let fat = &start[..512];
+
+    if ext[56..58] == [0x53, 0xef] {
+        write_uuid(&mut ids.uuid, &ext[104..120])?;
+        ids.label.push(until_nul(&ext[120..136]))?;
+    } else if start[..4] == *b"XFSB" {
+        write_uuid(&mut ids.uuid, &start[32..48])?;
+        ids.label.push(until_nul(&start[108..120]))?;
+    } else if btrfs[64..72] == *b"_BHRfS_M" {
+        write_uuid(&mut ids.uuid, &btrfs[32..48])?;
+        ids.label.push(until_nul(&btrfs[299..299 + 255]))?;
+    } else if fat[510..512] == [0x55, 0xaa]
+        && (fat[82..87] == *b"FAT32" || fat[54..57] == *b"FAT")
+    {
+        // FAT32 moved the volume ID and label further in
+        let (id, label) = if fat[82..87] == *b"FAT32" {
+            (&fat[67..71], &fat[71..82])
+        } else {
+            (&fat[39..43], &fat[43..54])
+        };
+
+        write!(
+            ids.uuid,
+            "{:02X}{:02X}-{:02X}{:02X}",
+            id[3], id[2], id[1], id[0]
+        )
+        .map_err(|_| Error::WriteToString)?;
+
+        let label = until_nul(label).trim_ascii_end();
+        if label != b"NO NAME" {
+            ids.label.push(label)?;
+        }
+    } else {
+        return Ok(None);
+    }
+
+    Ok(Some(ids))
+}
+
+/// The UUID of partition `number` of `disk`, from its GPT or MBR
+/// partition table
+fn partition_uuid(
+    disk: &CStr,
+    number: u32,
+) -> crate::Result<Option<Uuid>> {
+    let mut mbr = [0; 512];
+    let mut header = [0; 512];
+    // GPT headers are in the second logical block, whose size we
+    // don't know yet
+    let mut header_4k = [0; 512];
+    read_at(
+        disk,
+        &mut [
+            (0, &mut mbr),
+            (512, &mut header),
+            (4096, &mut header_4k),
+        ],
+    )?;
+
+    let mut uuid = Uuid::new();
+
+    let (header, block_size) = if header[..8] == *b"EFI PART" {
+        (&header, 512)
+    } else if header_4k[..8] == *b"EFI PART" {
+        (&header_4k, 4096)
+    } else if mbr[510..512] == [0x55, 0xaa] {
+        // Made of the disk signature and the partition number
+        let signature = u32::from_le_bytes([
+            mbr[440], mbr[441], mbr[442], mbr[443],
+        ]);
+        write!(uuid, "{signature:08x}-{number:02x}")
+            .map_err(|_| Error::WriteToString)?;
+        return Ok(Some(uuid));
+    } else {
+        return Ok(None);
+    };
+
+    let le_u32 = |bytes: &[u8]| {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    let entries_lba = u64::from(le_u32(&header[72..76]))
+        | u64::from(le_u32(&header[76..80])) << 32;
+    let entry_count = le_u32(&header[80..84]);
+    let entry_size = u64::from(le_u32(&header[84..88]));
+
+    if number == 0 || number > entry_count || entry_size < 128 {
+        return Ok(None);
+    }
+
+    let offset =
+        entries_lba * block_size + u64::from(number - 1) * entry_size;
+    let mut entry = [0; 128];
+    read_at(disk, &mut [(offset, &mut entry)])?;
+
+    // The first three fields of a GUID are little-endian
+    let guid = &entry[16..32];
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(guid);
+    bytes[..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    write_uuid(&mut uuid, &bytes)?;
+
+    Ok(Some(uuid))
+}
+
+/// Reads each buffer from its offset in `device`. Whatever is past
+/// the end of the device is left zeroed.
+fn read_at(
+    device: &CStr,
+    reads: &mut [(u64, &mut [u8])],
+) -> crate::Result<()> {
+    let fd = open(
+        device,
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    let result = reads.iter_mut().try_for_each(|(offset, buffer)| {
+        let offset = i64::try_from(*offset)
+            .map_err(|_| Error::DeviceNotFound)?;
+        pread(fd, buffer, offset)?;
+        Ok(())
+    });
+
+    let _ = close(fd);
+
+    result
+}
+
+/// Formats 16 bytes as e.g. `0b3f4c7e-2f1a-4d1e-9c6b-1a2b3c4d5e6f`
+fn write_uuid(uuid: &mut Uuid, bytes: &[u8]) -> crate::Result<()> {
+    for (idx, byte) in bytes.iter().enumerate() {
+        if matches!(idx, 4 | 6 | 8 | 10) {
+            uuid.push(b"-")?;
+        }
+        write!(uuid, "{byte:02x}")
+            .map_err(|_| Error::WriteToString)?;
+    }
+
+    Ok(())
+}
+
+fn until_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, format, fs, vec};
+
+    use super::*;
+
+    const UUID_BYTES: [u8; 16] = [
+        0x0b, 0x3f, 0x4c, 0x7e, 0x2f, 0x1a, 0x4d, 0x1e, 0x9c, 0x6b,
+        0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f,
+    ];
+    const UUID: &[u8] = b"0b3f4c7e-2f1a-4d1e-9c6b-1a2b3c4d5e6f";
+
+    /// A disk image made of `writes` at their offsets, which is
+    /// removed once dropped
+    struct Image(CString);
+
+    impl Image {
+        fn new(name: &str, writes: &[(usize, &[u8])]) -> Self {
+            let mut bytes = vec![0; 0x11000];
+            for (offset, data) in writes {
+                bytes[*offset..offset + data.len()]
+                    .copy_from_slice(data);
+            }
+
+            let path = std::env::temp_dir().join(format!(
+                "incipio-{}-{name}",
+                std::process::id()
+            ));
+            fs::write(&path, bytes).unwrap();
+
+            Self(CString::new(path.to_str().unwrap()).unwrap())
+        }
+    }
+
+    impl Drop for Image {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0.to_str().unwrap());
+        }
+    }
+
+    fn ids(image: &Image) -> Option<(Uuid, FixedCStr<256>)> {
+        filesystem_ids(&image.0)
+            .unwrap()
+            .map(|ids| (ids.uuid, ids.label))
+    }
+
+    #[test]
+    fn splits_tags() {
+        assert_eq!(
+            Tag::parse(b"UUID=1234-5678"),
+            Some((Tag::Uuid, &b"1234-5678"[..]))
+        );
+        assert_eq!(
+            Tag::parse(b"PARTLABEL=root"),
+            Some((Tag::PartLabel, &b"root"[..]))
+        );
+        assert_eq!(Tag::parse(b"/dev/sda1"), None);
+    }
+
+    #[test]
+    fn probes_ext() {
+        let image = Image::new(
+            "ext",
+            &[
+                (1024 + 56, &[0x53, 0xef]),
+                (1024 + 104, &UUID_BYTES),
+                (1024 + 120, b"root"),
+            ],
+        );
+        let (uuid, label) = ids(&image).unwrap();
+
+        assert_eq!(uuid.as_bytes(), UUID);
+        assert_eq!(label.as_bytes(), b"root");
+    }
+
+    #[test]
+    fn probes_xfs() {
+        let image = Image::new(
+            "xfs",
+            &[(0, b"XFSB"), (32, &UUID_BYTES), (108, b"data")],
+        );
+        let (uuid, label) = ids(&image).unwrap();
+
+        assert_eq!(uuid.as_bytes(), UUID);
+        assert_eq!(label.as_bytes(), b"data");
+    }
+
+    #[test]
+    fn probes_btrfs() {
+        let image = Image::new(
+            "btrfs",
+            &[
+                (0x10000 + 64, b"_BHRfS_M"),
+                (0x10000 + 32, &UUID_BYTES),
+                (0x10000 + 299, b"home"),
+            ],
+        );
+        let (uuid, label) = ids(&image).unwrap();
+
+        assert_eq!(uuid.as_bytes(), UUID);
+        assert_eq!(label.as_bytes(), b"home");
+    }
+
+    #[test]
+    fn probes_fat32() {
+        let image = Image::new(
+            "fat32",
+            &[
+                (67, &[0x78, 0x56, 0x34, 0x12]),
+                (71, b"EFI        "),
+                (82, b"FAT32   "),
+                (510, &[0x55, 0xaa]),
+            ],
+        );
+        let (uuid, label) = ids(&image).unwrap();
+
+        assert_eq!(uuid.as_bytes(), b"1234-5678");
+        assert_eq!(label.as_bytes(), b"EFI");
+    }
+
+    #[test]
+    fn leaves_unnamed_fat_unlabelled() {
+        let image = Image::new(
+            "fat16",
+            &[
+                (39, &[0xef, 0xbe, 0xad, 0xde]),
+                (43, b"NO NAME    "),
+                (54, b"FAT16   "),
+                (510, &[0x55, 0xaa]),
+            ],
+        );
+        let (uuid, label) = ids(&image).unwrap();
+
+        assert_eq!(uuid.as_bytes(), b"DEAD-BEEF");
+        assert!(label.as_bytes().is_empty());
+    }
+
+    #[test]
+    fn ignores_unknown_filesystems() {
+        let image = Image::new("unknown", &[]);
+
+        assert!(ids(&image).is_none());
+    }
+
+    #[test]
+    fn reads_gpt_partition_uuids() {
+        // The first three fields of the GUID are little-endian
+        let mut guid = UUID_BYTES;
+        guid[..4].reverse();
+        guid[4..6].reverse();
+        guid[6..8].reverse();
+
+        let image = Image::new(
+            "gpt",
+            &[
+                (512, b"EFI PART"),
+                (512 + 72, &2_u64.to_le_bytes()),
+                (512 + 80, &4_u32.to_le_bytes()),
+                (512 + 84, &128_u32.to_le_bytes()),
+                // The second entry, in the third block
+                (1024 + 128 + 16, &guid),
+            ],
+        );
+
+        let uuid = partition_uuid(&image.0, 2).unwrap().unwrap();
+        assert_eq!(uuid.as_bytes(), UUID);
+        assert!(partition_uuid(&image.0, 5).unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_mbr_partition_uuids() {
+        let image = Image::new(
+            "mbr",
+            &[
+                (440, &0x1234_abcd_u32.to_le_bytes()),
+                (510, &[0x55, 0xaa]),
+            ],
+        );
+
+        let uuid = partition_uuid(&image.0, 2).unwrap().unwrap();
+        assert_eq!(uuid.as_bytes(), b"1234abcd-02");
+    }
+}
@@ -0,0 +1,212 @@
+use core::ffi::CStr;
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    errno::Errno,
+    mount::mount,
+    sys::stat::Mode,
+    unistd::{mkdir, symlinkat},
+};
+
+use super::options::MountOptions;
+use crate::{
+    fs::{is_mount_point, MountPointParser},
+    Error,
+};
+
+/// Replaces [`DEFAULT_EARLY_MOUNTS`] when it exists.
+///
+/// It's written in the format of `/etc/fstab`, e.g.
+///
+/// ```text
+/// proc    /proc                      proc      nosuid,noexec,nodev
+/// sys     /sys                       sysfs     nosuid,noexec,nodev
+/// run     /run                       tmpfs     nosuid,nodev,mode=0755
+/// dev     /dev                       devtmpfs  nosuid,mode=0755
+/// devpts  /dev/pts                   devpts    gid=5,mode=620
+/// shm     /run/shm                   tmpfs     nosuid,nodev,mode=1777
+/// cgroup2 /sys/fs/cgroup             cgroup2   nosuid,noexec,nodev
+/// efivars /sys/firmware/efi/efivars  efivarfs  nosuid,noexec,nodev,nofail
+/// ```
+///
+/// Entries are mounted in order, so they must come after the mounts
+/// they're nested in. Entries whose target is already a mount point
+/// are skipped. Failing to mount an entry aborts the boot,
+/// unless the entry has the `nofail` option.
+pub static EARLY_MOUNTS_PATH: &CStr =
+    cstr!("/etc/incipio/early-mounts");
+
+/// A filesystem mounted before anything else, which programs expect
+/// to be there no matter what
+pub struct EarlyMount {
+    pub source: &'static CStr,
+    pub target: &'static CStr,
+    pub filesystem_type: &'static CStr,
+    /// Mount options, as they'd be written in `/etc/fstab`
+    pub options: &'static [u8],
+}
+
+/// The early mounts used when [`EARLY_MOUNTS_PATH`] doesn't exist
+pub const DEFAULT_EARLY_MOUNTS: [EarlyMount; 7] = [
+    EarlyMount {
+        source: cstr!("proc"),
+        target: cstr!("/proc"),
+        filesystem_type: cstr!("proc"),
+        options: b"nosuid,noexec,nodev",
+    },
+    EarlyMount {
+        source: cstr!("sys"),
+        target: cstr!("/sys"),
+        filesystem_type: cstr!("sysfs"),
+        options: b"nosuid,noexec,nodev",
+    },
+    EarlyMount {
+        source: cstr!("run"),
+        target: cstr!("/run"),
+        filesystem_type: cstr!("tmpfs"),
+        options: b"nosuid,nodev,mode=0755",
+    },
+    EarlyMount {
+        source: cstr!("dev"),
+        target: cstr!("/dev"),
+        filesystem_type: cstr!("devtmpfs"),
+        options: b"nosuid,mode=0755",
+    },
+    // Group 5 is `tty`, which terminal emulators and the like rely
+    // on being able to write to pseudo-terminals
+    EarlyMount {
+        source: cstr!("devpts"),
+        target: cstr!("/dev/pts"),
+        filesystem_type: cstr!("devpts"),
+        options: b"nosuid,noexec,gid=5,mode=620,ptmxmode=000",
+    },
+    EarlyMount {
+        source: cstr!("shm"),
+        target: cstr!("/dev/shm"),
+        filesystem_type: cstr!("tmpfs"),
+        options: b"nosuid,nodev,mode=1777",
+    },
+    EarlyMount {
+        source: cstr!("lock"),
+        target: cstr!("/run/lock"),
+        filesystem_type: cstr!("tmpfs"),
+        options: b"nosuid,nodev,noexec,mode=1777",
+    },
+];
+
+static DEV: &CStr = cstr!("/dev");
+
+/// Symbolic links every `/dev` is expected to have, which devtmpfs
+/// doesn't create, as `(link, target)` pairs
+const DEV_SYMLINKS: [(&CStr, &CStr); 5] = [
+    (cstr!("/dev/fd"), cstr!("/proc/self/fd")),
+    (cstr!("/dev/stdin"), cstr!("/proc/self/fd/0")),
+    (cstr!("/dev/stdout"), cstr!("/proc/self/fd/1")),
+    (cstr!("/dev/stderr"), cstr!("/proc/self/fd/2")),
+    (cstr!("/dev/core"), cstr!("/proc/kcore")),
+];
+
+/// Mounts the entries of [`EARLY_MOUNTS_PATH`] or, if it doesn't
+/// exist, of [`DEFAULT_EARLY_MOUNTS`].
+pub fn mount_early_filesystems() -> crate::Result<()> {
+    let parser = match MountPointParser::new(EARLY_MOUNTS_PATH) {
+        Ok(parser) => parser,
+        Err(Error::Errno(Errno::ENOENT)) => {
+            return DEFAULT_EARLY_MOUNTS.iter().try_for_each(
+                |entry| {
+                    mount_early(
+                        entry.source,
+                        entry.target,
+                        entry.filesystem_type,
+                        entry.options,
+                    )
+                },
+            );
+        }
+        Err(err) => return Err(err),
+    };
+
+    for entry in parser {
+        mount_early(
+            entry.fsname(),
+            entry.path(),
+            entry.filesystem_type(),
+            entry.options().to_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn mount_early(
+    source: &CStr,
+    target: &CStr,
+    filesystem_type: &CStr,
+    options: &[u8],
+) -> crate::Result<()> {
+    let options = MountOptions::parse(options)?;
+
+    // Under an initramfs or a container, whoever ran us may have
+    // mounted these already
+    let result = match is_mount_point(target) {
+        Ok(true) => Ok(()),
+        _ => create_mount_point(target).and_then(|()| {
+            mount(
+                Some(source),
+                target,
+                Some(filesystem_type),
+                options.flags,
+                Some(options.data.as_cstr()),
+            )
+        }),
+    };
+
+    match result {
+        Ok(()) => {
+            // Even the rescue shell expects /dev/fd and friends
+            if target == DEV {
+                create_dev_symlinks();
+            }
+            Ok(())
+        }
+        Err(errno) if options.nofail => {
+            libc_eprintln!("Failed to mount {:?}: {}", target, errno);
+            Ok(())
+        }
+        Err(errno) => Err(errno.into()),
+    }
+}
+
+/// Creates `target` if it doesn't exist, as is the case of e.g.
+/// `/run/shm` or of `/dev` subdirectories
+fn create_mount_point(target: &CStr) -> nix::Result<()> {
+    match mkdir(
+        target,
+        Mode::S_IRWXU
+            | Mode::S_IRGRP
+            | Mode::S_IXGRP
+            | Mode::S_IROTH
+            | Mode::S_IXOTH,
+    ) {
+        Ok(()) | Err(Errno::EEXIST) => Ok(()),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// Creates the symbolic links of [`DEV_SYMLINKS`], leaving alone
+/// the ones that already exist.
+fn create_dev_symlinks() {
+    for (link, target) in DEV_SYMLINKS {
+        match symlinkat(target, None, link) {
+            Ok(()) | Err(Errno::EEXIST) => {}
+            Err(errno) => {
+                libc_eprintln!(
+                    "Failed to create {:?}: {}",
+                    link,
+                    errno
+                )
+            }
+        }
+    }
+}
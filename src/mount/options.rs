@@ -0,0 +1,156 @@
+use nix::mount::MsFlags;
+
+use crate::fixed::FixedCStr;
+
+/// Filesystem-specific options, passed as the `data` argument of
+/// `mount(2)`
+pub type MountData = FixedCStr<256>;
+
+/// The options of an fstab entry, split into what `mount(2)` takes
+/// as flags and what it takes as data.
+pub struct MountOptions {
+    pub flags: MsFlags,
+    pub data: MountData,
+    /// The entry is not mounted at boot
+    pub noauto: bool,
+    /// Failing to mount the entry is not an error
+    pub nofail: bool,
+}
+
+impl MountOptions {
+    /// Translates a comma-separated option string, e.g.
+    /// `ro,noatime,mode=0755`.
+    ///
+    /// Options understood by the kernel as flags become [`MsFlags`],
+    /// options only meaningful to userspace tools (`user`, `nofail`,
+    /// `x-*`, ...) are dropped and everything else is passed on to
+    /// the filesystem as data.
+    pub fn parse(options: &[u8]) -> crate::Result<Self> {
+        let mut parsed = Self {
+            flags: MsFlags::empty(),
+            data: MountData::new(),
+            noauto: false,
+            nofail: false,
+        };
+
+        for option in options.split(|&byte| byte == b',') {
+            if option == b"noauto" {
+                parsed.noauto = true;
+            } else if option == b"nofail" {
+                parsed.nofail = true;
+            } else if let Some((flag, set)) = flag_option(option) {
+                parsed.flags.set(flag, set);
+            } else if !is_userspace_option(option) {
+                if !parsed.data.as_bytes().is_empty() {
+                    parsed.data.push(b",")?;
+                }
+                parsed.data.push(option)?;
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// The flag an option sets or, if the boolean is false, clears
+fn flag_option(option: &[u8]) -> Option<(MsFlags, bool)> {
+    let flag = match option {
+        b"ro" => (MsFlags::MS_RDONLY, true),
+        b"rw" => (MsFlags::MS_RDONLY, false),
+        b"nosuid" => (MsFlags::MS_NOSUID, true),
+        b"suid" => (MsFlags::MS_NOSUID, false),
+        b"nodev" => (MsFlags::MS_NODEV, true),
+        b"dev" => (MsFlags::MS_NODEV, false),
+        b"noexec" => (MsFlags::MS_NOEXEC, true),
+        b"exec" => (MsFlags::MS_NOEXEC, false),
+        b"sync" => (MsFlags::MS_SYNCHRONOUS, true),
+        b"async" => (MsFlags::MS_SYNCHRONOUS, false),
+        b"dirsync" => (MsFlags::MS_DIRSYNC, true),
+        b"mand" => (MsFlags::MS_MANDLOCK, true),
+        b"nomand" => (MsFlags::MS_MANDLOCK, false),
+        b"noatime" => (MsFlags::MS_NOATIME, true),
+        b"atime" => (MsFlags::MS_NOATIME, false),
+        b"nodiratime" => (MsFlags::MS_NODIRATIME, true),
+        b"diratime" => (MsFlags::MS_NODIRATIME, false),
+        b"relatime" => (MsFlags::MS_RELATIME, true),
+        b"norelatime" => (MsFlags::MS_RELATIME, false),
+        b"strictatime" => (MsFlags::MS_STRICTATIME, true),
+        b"nostrictatime" => (MsFlags::MS_STRICTATIME, false),
+        b"lazytime" => (MsFlags::MS_LAZYTIME, true),
+        b"nolazytime" => (MsFlags::MS_LAZYTIME, false),
+        b"iversion" => (MsFlags::MS_I_VERSION, true),
+        b"noiversion" => (MsFlags::MS_I_VERSION, false),
+        b"silent" => (MsFlags::MS_SILENT, true),
+        b"loud" => (MsFlags::MS_SILENT, false),
+        b"bind" => (MsFlags::MS_BIND, true),
+        b"rbind" => (MsFlags::MS_BIND | MsFlags::MS_REC, true),
+        b"remount" => (MsFlags::MS_REMOUNT, true),
+        _ => return None,
+    };
+
+    Some(flag)
+}
+
+/// Whether `option` only means something to `mount(8)` and friends,
+/// so that the kernel would reject it
+fn is_userspace_option(option: &[u8]) -> bool {
+    matches!(
+        option,
+        b"" | b"defaults"
+            | b"auto"
+            | b"user"
+            | b"nouser"
+            | b"users"
+            | b"owner"
+            | b"group"
+            | b"_netdev"
+    ) || option.starts_with(b"x-")
+        || option.starts_with(b"comment=")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_flags_from_data() {
+        let options =
+            MountOptions::parse(b"ro,nosuid,mode=0755,size=10%")
+                .unwrap();
+
+        assert_eq!(
+            options.flags,
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID
+        );
+        assert_eq!(options.data.as_bytes(), b"mode=0755,size=10%");
+        assert!(!options.noauto);
+        assert!(!options.nofail);
+    }
+
+    #[test]
+    fn later_options_override_earlier_ones() {
+        let options = MountOptions::parse(b"ro,noexec,rw").unwrap();
+
+        assert_eq!(options.flags, MsFlags::MS_NOEXEC);
+    }
+
+    #[test]
+    fn drops_userspace_options() {
+        let options = MountOptions::parse(
+            b"defaults,noauto,nofail,user,_netdev,x-systemd.foo=1",
+        )
+        .unwrap();
+
+        assert!(options.flags.is_empty());
+        assert!(options.data.as_bytes().is_empty());
+        assert!(options.noauto);
+        assert!(options.nofail);
+    }
+
+    #[test]
+    fn recursive_bind() {
+        let options = MountOptions::parse(b"rbind").unwrap();
+
+        assert_eq!(options.flags, MsFlags::MS_BIND | MsFlags::MS_REC);
+    }
+}
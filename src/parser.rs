@@ -0,0 +1,122 @@
+use crate::Error;
+
+/// Iterates over the `key = value` lines of a configuration file.
+///
+/// Empty lines and lines starting with `#` are skipped, and both
+/// keys and values are trimmed of surrounding whitespace. Lines
+/// without an `=` sign are reported as errors.
+pub struct KeyValues<'a> {
+    rest: &'a [u8],
+    line_number: usize,
+}
+
+impl<'a> KeyValues<'a> {
+    pub fn new(contents: &'a [u8]) -> Self {
+        Self {
+            rest: contents,
+            line_number: 0,
+        }
+    }
+
+    /// The line number of the last pair returned, starting from 1.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn next_line(&mut self) -> Option<&'a [u8]> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let (line, rest) =
+            match self.rest.iter().position(|&byte| byte == b'\n') {
+                Some(idx) => {
+                    (&self.rest[..idx], &self.rest[idx + 1..])
+                }
+                None => (self.rest, &[][..]),
+            };
+
+        self.rest = rest;
+        self.line_number += 1;
+
+        Some(line)
+    }
+}
+
+impl<'a> Iterator for KeyValues<'a> {
+    type Item = crate::Result<(&'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.next_line()?.trim_ascii();
+
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+
+            let Some(idx) =
+                line.iter().position(|&byte| byte == b'=')
+            else {
+                return Some(Err(Error::ConfigSyntax));
+            };
+
+            let key = line[..idx].trim_ascii();
+            let value = line[idx + 1..].trim_ascii();
+
+            if key.is_empty() {
+                return Some(Err(Error::ConfigSyntax));
+            }
+
+            return Some(Ok((key, value)));
+        }
+    }
+}
+
+/// Iterates over the whitespace-separated words of `value`.
+///
+/// A word wrapped in double quotes may contain whitespace, in which
+/// case the quotes themselves are not part of the word returned.
+pub struct Words<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Words<'a> {
+    pub fn new(value: &'a [u8]) -> Self {
+        Self { rest: value }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_ascii_start();
+
+        let (word, rest) = match self.rest {
+            [] => return None,
+            [b'"', quoted @ ..] => {
+                match quoted.iter().position(|&byte| byte == b'"') {
+                    Some(idx) => (&quoted[..idx], &quoted[idx + 1..]),
+                    // Unterminated quotes go on until the end
+                    None => (quoted, &[][..]),
+                }
+            }
+            unquoted => {
+                let end = unquoted
+                    .iter()
+                    .position(u8::is_ascii_whitespace)
+                    .unwrap_or(unquoted.len());
+                (&unquoted[..end], &unquoted[end..])
+            }
+        };
+
+        self.rest = rest;
+
+        Some(word)
+    }
+}
+
+/// Parses a non-negative decimal number
+pub fn parse_number(bytes: &[u8]) -> Option<u32> {
+    core::str::from_utf8(bytes).ok()?.parse().ok()
+}
@@ -0,0 +1,268 @@
+use core::ffi::CStr;
+
+use cstr::cstr;
+use nix::{
+    errno::Errno,
+    unistd::{Gid, Uid},
+};
+
+use crate::{parser::parse_number, utils::FileMapping, Error};
+
+static PASSWD: &CStr = cstr!("/etc/passwd");
+static GROUP: &CStr = cstr!("/etc/group");
+
+/// The most groups a process is given, its primary group included
+pub const MAX_GROUPS: usize = 32;
+
+/// The user and groups a process runs as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+    /// The primary group, followed by the supplementary ones
+    groups: [Gid; MAX_GROUPS],
+    group_count: usize,
+}
+
+impl Credentials {
+    fn new(uid: Uid, gid: Gid) -> Self {
+        Self {
+            uid,
+            gid,
+            groups: [gid; MAX_GROUPS],
+            group_count: 1,
+        }
+    }
+
+    /// The groups to give to `setgroups(2)`
+    pub fn groups(&self) -> &[Gid] {
+        &self.groups[..self.group_count]
+    }
+
+    /// Adds the groups of `/etc/group` that list `name` as one of
+    /// their members, like `initgroups(3)` does
+    fn add_supplementary_groups(
+        &mut self,
+        name: &[u8],
+    ) -> crate::Result<()> {
+        let mut mapping = match FileMapping::open(GROUP) {
+            Ok(mapping) => mapping,
+            Err(
+                Error::Errno(Errno::ENOENT)
+                | Error::UnexpectedEmptyFile,
+            ) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let result = mapping
+            .as_slice()
+            .split(|&byte| byte == b'\n')
+            .filter_map(GroupEntry::parse)
+            .filter(|group| group.has_member(name))
+            .try_for_each(|group| self.add_group(group.gid));
+
+        mapping.close()?;
+
+        result
+    }
+
+    fn add_group(&mut self, gid: Gid) -> crate::Result<()> {
+        if self.groups().contains(&gid) {
+            return Ok(());
+        }
+
+        let slot = self
+            .groups
+            .get_mut(self.group_count)
+            .ok_or(Error::CapacityExceeded)?;
+        *slot = gid;
+        self.group_count += 1;
+
+        Ok(())
+    }
+}
+
+/// An entry of `/etc/passwd`, i.e.
+/// `name:password:uid:gid:gecos:home:shell`
+struct PasswdEntry<'a> {
+    name: &'a [u8],
+    credentials: Credentials,
+}
+
+impl<'a> PasswdEntry<'a> {
+    fn parse(line: &'a [u8]) -> Option<Self> {
+        let mut fields = line.split(|&byte| byte == b':');
+
+        let name = fields.next()?;
+        let _password = fields.next()?;
+        let uid = parse_number(fields.next()?)?;
+        let gid = parse_number(fields.next()?)?;
+
+        Some(Self {
+            name,
+            credentials: Credentials::new(
+                Uid::from_raw(uid),
+                Gid::from_raw(gid),
+            ),
+        })
+    }
+}
+
+/// An entry of `/etc/group`, i.e. `name:password:gid:members`,
+/// where members are comma-separated user names
+struct GroupEntry<'a> {
+    gid: Gid,
+    members: &'a [u8],
+}
+
+impl<'a> GroupEntry<'a> {
+    fn parse(line: &'a [u8]) -> Option<Self> {
+        let mut fields = line.split(|&byte| byte == b':');
+
+        let _name = fields.next()?;
+        let _password = fields.next()?;
+        let gid = parse_number(fields.next()?)?;
+        let members = fields.next()?;
+
+        Some(Self {
+            gid: Gid::from_raw(gid),
+            members,
+        })
+    }
+
+    fn has_member(&self, name: &[u8]) -> bool {
+        // A group without members still splits into one empty name
+        !name.is_empty()
+            && self
+                .members
+                .split(|&byte| byte == b',')
+                .any(|member| member == name)
+    }
+}
+
+/// Returns the credentials of the first `/etc/passwd` entry that
+/// matches `predicate`.
+fn find_entry(
+    predicate: impl Fn(&PasswdEntry) -> bool,
+) -> crate::Result<Option<Credentials>> {
+    let mut mapping = FileMapping::open(PASSWD)?;
+
+    let credentials = mapping
+        .as_slice()
+        .split(|&byte| byte == b'\n')
+        .filter_map(PasswdEntry::parse)
+        .find(|entry| predicate(entry))
+        .map(|entry| {
+            let mut credentials = entry.credentials;
+            credentials
+                .add_supplementary_groups(entry.name)
+                .map(|()| credentials)
+        })
+        .transpose();
+
+    mapping.close()?;
+
+    credentials
+}
+
+/// Resolves `user` into the credentials a process should run as.
+///
+/// `user` may be a user name found in `/etc/passwd`, a numeric UID
+/// or an explicit `uid:gid` pair. A bare UID gets the primary group
+/// of its `/etc/passwd` entry, if there's one, or a GID with the
+/// same value otherwise.
+///
+/// Users found in `/etc/passwd` also get the supplementary groups
+/// `/etc/group` lists them in, while a `uid:gid` pair gets no
+/// supplementary groups at all.
+pub fn lookup_user(user: &[u8]) -> crate::Result<Credentials> {
+    if let Some(idx) = user.iter().position(|&byte| byte == b':') {
+        let (uid, gid) = (&user[..idx], &user[idx + 1..]);
+
+        return match (parse_number(uid), parse_number(gid)) {
+            (Some(uid), Some(gid)) => Ok(Credentials::new(
+                Uid::from_raw(uid),
+                Gid::from_raw(gid),
+            )),
+            _ => Err(Error::UnknownUser),
+        };
+    }
+
+    if let Some(uid) = parse_number(user) {
+        let uid = Uid::from_raw(uid);
+        let credentials =
+            find_entry(|entry| entry.credentials.uid == uid)
+                .ok()
+                .flatten();
+
+        return Ok(credentials.unwrap_or(Credentials::new(
+            uid,
+            Gid::from_raw(uid.as_raw()),
+        )));
+    }
+
+    find_entry(|entry| entry.name == user)?.ok_or(Error::UnknownUser)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_passwd_entries() {
+        let entry = PasswdEntry::parse(
+            b"sshd:x:74:74:sshd:/var/empty:/bin/false",
+        )
+        .unwrap();
+
+        assert_eq!(entry.name, b"sshd");
+        assert_eq!(entry.credentials.uid, Uid::from_raw(74));
+        assert_eq!(entry.credentials.gid, Gid::from_raw(74));
+        assert_eq!(entry.credentials.groups(), [Gid::from_raw(74)]);
+    }
+
+    #[test]
+    fn rejects_malformed_passwd_entries() {
+        assert!(PasswdEntry::parse(b"").is_none());
+        assert!(PasswdEntry::parse(b"# comment").is_none());
+        assert!(PasswdEntry::parse(b"sshd:x:sshd:74").is_none());
+    }
+
+    #[test]
+    fn parses_group_members() {
+        let group =
+            GroupEntry::parse(b"wheel:x:10:root,alice").unwrap();
+
+        assert_eq!(group.gid, Gid::from_raw(10));
+        assert!(group.has_member(b"alice"));
+        assert!(!group.has_member(b"ali"));
+
+        let empty = GroupEntry::parse(b"audio:x:11:").unwrap();
+        assert!(!empty.has_member(b""));
+    }
+
+    #[test]
+    fn adds_each_group_once() {
+        let mut credentials =
+            Credentials::new(Uid::from_raw(1000), Gid::from_raw(100));
+
+        credentials.add_group(Gid::from_raw(10)).unwrap();
+        credentials.add_group(Gid::from_raw(100)).unwrap();
+
+        assert_eq!(
+            credentials.groups(),
+            [Gid::from_raw(100), Gid::from_raw(10)]
+        );
+    }
+
+    #[test]
+    fn takes_explicit_uid_and_gid() {
+        let credentials = lookup_user(b"1000:100").unwrap();
+
+        assert_eq!(
+            credentials,
+            Credentials::new(Uid::from_raw(1000), Gid::from_raw(100))
+        );
+        assert!(lookup_user(b"1000:users").is_err());
+    }
+}
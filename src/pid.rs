@@ -1,4 +1,20 @@
-use nix::unistd::getpid;
+use core::fmt::Write;
+
+use cstr::cstr;
+use nix::{
+    dir::Dir,
+    fcntl::OFlag,
+    sys::stat::Mode,
+    unistd::{getpid, Pid},
+};
+
+use crate::{
+    fixed::FixedCStr, parser::parse_number, utils::read_file, Error,
+};
+
+/// The `PF_KTHREAD` bit of the flags in `/proc/<pid>/stat`, set for
+/// kernel threads
+const PF_KTHREAD: u32 = 0x0020_0000;
 
 /// Returns true if the process is currently being run as the
 /// init system (PID 1)
@@ -9,3 +25,71 @@ pub fn ensure_running_as_init_system() -> crate::error::Result<()> {
         Err(crate::error::Error::NotRunningAsInitSystem)
     }
 }
+
+/// Calls `f` with the PID of every live userspace process but
+/// incipio itself, as listed in `/proc`.
+///
+/// Kernel threads and zombies are skipped, since signals have no
+/// effect on either of them.
+pub fn for_each_process(mut f: impl FnMut(Pid)) -> crate::Result<()> {
+    let mut proc = Dir::open(
+        cstr!("/proc"),
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    for entry in proc.iter() {
+        let entry = entry?;
+
+        // Every directory named after a number is a process
+        let Some(pid) = parse_number(entry.file_name().to_bytes())
+        else {
+            continue;
+        };
+
+        if pid == 1 {
+            continue;
+        }
+
+        // The process may exit while we look at it
+        let pid = Pid::from_raw(pid as i32);
+        if let Ok(true) = is_userspace_process(pid) {
+            f(pid);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `pid` is a live process that is not a kernel thread,
+/// according to `/proc/<pid>/stat`.
+fn is_userspace_process(pid: Pid) -> crate::Result<bool> {
+    let mut path = FixedCStr::<32>::new();
+    write!(path, "/proc/{pid}/stat")
+        .map_err(|_| Error::WriteToString)?;
+
+    let mut stat = [0; 512];
+    let len = read_file(path.as_cstr(), &mut stat)?;
+
+    // The command name comes in parentheses and may contain spaces
+    // or parentheses itself, so the fields we want are found after
+    // the last closing parenthesis: the state is the first one and
+    // the flags are the seventh
+    let fields = stat[..len]
+        .iter()
+        .rposition(|&byte| byte == b')')
+        .map(|idx| &stat[idx + 1..len])
+        .ok_or(Error::UnexpectedEmptyFile)?;
+    let mut fields = fields
+        .split(u8::is_ascii_whitespace)
+        .filter(|field| !field.is_empty());
+
+    let state = fields.next().and_then(|state| state.first());
+    let flags = fields.nth(5).and_then(parse_number);
+
+    match (state, flags) {
+        (Some(b'Z'), _) => Ok(false),
+        (Some(_), Some(flags)) => Ok(flags & PF_KTHREAD == 0),
+        _ => Err(Error::UnexpectedEmptyFile),
+    }
+}
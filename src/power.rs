@@ -0,0 +1,180 @@
+use core::ffi::{c_int, CStr};
+
+use libc_print::libc_eprintln;
+use nix::{
+    errno::Errno,
+    libc::{
+        reboot, EXIT_FAILURE, EXIT_SUCCESS, LINUX_REBOOT_CMD_HALT,
+        LINUX_REBOOT_CMD_POWER_OFF, LINUX_REBOOT_CMD_RESTART,
+    },
+    sys::signal::kill,
+    unistd::{sync, Pid},
+};
+
+use crate::{
+    config::Config,
+    control::{
+        client::send_request,
+        protocol::{Command, Request, Status, MAX_RESPONSE_SIZE},
+    },
+};
+
+/// Exit status used for invalid command lines
+const EXIT_USAGE: isize = 2;
+
+/// The ways the machine can be brought down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    PowerOff,
+    Reboot,
+    Halt,
+}
+
+impl PowerAction {
+    /// The `reboot(2)` command carrying out this action
+    pub fn reboot_command(self) -> c_int {
+        match self {
+            PowerAction::PowerOff => LINUX_REBOOT_CMD_POWER_OFF,
+            PowerAction::Reboot => LINUX_REBOOT_CMD_RESTART,
+            PowerAction::Halt => LINUX_REBOOT_CMD_HALT,
+        }
+    }
+
+    fn command(self) -> Command {
+        match self {
+            PowerAction::PowerOff => Command::PowerOff,
+            PowerAction::Reboot => Command::Reboot,
+            PowerAction::Halt => Command::Halt,
+        }
+    }
+}
+
+/// The commands incipio provides when run through a symlink with
+/// the command's name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerCommand {
+    /// `poweroff`, `reboot` or `halt`
+    Direct(PowerAction),
+    /// `shutdown`, which takes the action as a flag
+    Shutdown,
+}
+
+impl PowerCommand {
+    /// Figures out the command to run from the name we were run as,
+    /// i.e. `argv[0]`. Returns `None` when run as plain incipio.
+    pub fn from_program_name(program: &CStr) -> Option<Self> {
+        let program = program.to_bytes();
+        let name =
+            match program.iter().rposition(|&byte| byte == b'/') {
+                Some(idx) => &program[idx + 1..],
+                None => program,
+            };
+
+        match name {
+            b"poweroff" => Some(Self::Direct(PowerAction::PowerOff)),
+            b"reboot" => Some(Self::Direct(PowerAction::Reboot)),
+            b"halt" => Some(Self::Direct(PowerAction::Halt)),
+            b"shutdown" => Some(Self::Shutdown),
+            _ => None,
+        }
+    }
+
+    fn usage(self) -> &'static str {
+        match self {
+            PowerCommand::Direct(_) => "[-f|--force]",
+            PowerCommand::Shutdown => "[-r|-h|-P|-H] [now]",
+        }
+    }
+
+    /// Runs the command with the given arguments (not including
+    /// `argv[0]`), returning the exit status.
+    pub fn run<'a>(
+        self,
+        program: &CStr,
+        arguments: impl Iterator<Item = &'a CStr>,
+    ) -> isize {
+        let mut action = match self {
+            PowerCommand::Direct(action) => action,
+            PowerCommand::Shutdown => PowerAction::PowerOff,
+        };
+        let mut force = false;
+
+        for argument in arguments {
+            match (self, argument.to_bytes()) {
+                (PowerCommand::Direct(_), b"-f" | b"--force") => {
+                    force = true
+                }
+                (PowerCommand::Shutdown, b"-r") => {
+                    action = PowerAction::Reboot
+                }
+                (PowerCommand::Shutdown, b"-h" | b"-P") => {
+                    action = PowerAction::PowerOff
+                }
+                (PowerCommand::Shutdown, b"-H") => {
+                    action = PowerAction::Halt
+                }
+                // Scheduled shutdowns are not supported, so the only
+                // time accepted is right now
+                (PowerCommand::Shutdown, b"now" | b"+0") => {}
+                _ => {
+                    libc_eprintln!(
+                        "Usage: {:?} {}",
+                        program,
+                        self.usage()
+                    );
+                    return EXIT_USAGE;
+                }
+            }
+        }
+
+        let result = if force {
+            force_power_action(action)
+        } else {
+            request_power_action(action)
+        };
+
+        match result {
+            Ok(()) => EXIT_SUCCESS as isize,
+            Err(errno) => {
+                libc_eprintln!("{:?}: {}", program, errno);
+                EXIT_FAILURE as isize
+            }
+        }
+    }
+}
+
+/// Brings the machine down right away, without stopping processes
+/// or unmounting filesystems.
+pub fn force_power_action(action: PowerAction) -> nix::Result<()> {
+    sync();
+
+    let ret_val = unsafe { reboot(action.reboot_command()) };
+
+    Errno::result(ret_val).map(drop)
+}
+
+/// Asks PID 1 to bring the machine down, through the control socket
+/// or, if it can't be reached, through a signal.
+fn request_power_action(action: PowerAction) -> nix::Result<()> {
+    let request = Request {
+        command: action.command(),
+        argument: &[],
+    };
+    let mut response = [0; MAX_RESPONSE_SIZE];
+
+    match send_request(&request, &mut response) {
+        Ok((Status::Ok, _)) => Ok(()),
+        Ok((Status::Error, message)) => {
+            libc_eprintln!(
+                "incipio refused the request: {}",
+                core::str::from_utf8(message).unwrap_or("")
+            );
+            Err(Errno::ECANCELED)
+        }
+        // The signals PID 1 reacts to depend on its configuration
+        Err(_) => {
+            let signal = Config::load().signal_profile.signal(action);
+            kill(Pid::from_raw(1), signal)
+        }
+    }
+}
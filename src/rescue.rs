@@ -0,0 +1,44 @@
+use libc_print::libc_eprintln;
+
+use crate::{
+    boot::kill_all_processes, config::Config,
+    emergency::run_console_shell, supervisor::Supervisor,
+};
+
+/// Runs a root shell on the console, going on with the boot once
+/// it exits
+pub fn rescue_shell(reason: &str, config: &Config) {
+    libc_eprintln!(
+        "{}, the boot continues once the shell exits",
+        reason
+    );
+
+    if let Err(err) = run_console_shell(config.emergency_shell()) {
+        libc_eprintln!(
+            "Failed to start a shell: {}",
+            err.description()
+        );
+    }
+}
+
+/// Brings a running system down to single-user mode, like
+/// sysvinit's runlevel S: every process is killed and a root shell
+/// runs on the console. Once it exits, every service and getty is
+/// started again.
+///
+/// Nothing else is handled while the shell runs, shutdown requests
+/// included.
+pub fn enter_rescue_mode(
+    supervisor: &mut Supervisor,
+    config: &Config,
+) {
+    libc_eprintln!("Entering rescue mode");
+
+    kill_all_processes(config.kill_timeout);
+    // Their exits were reaped along the way
+    supervisor.forget_all();
+
+    rescue_shell("Rescue mode requested", config);
+
+    supervisor.start_all();
+}
@@ -0,0 +1,212 @@
+use core::{
+    ffi::{c_int, c_uint, CStr},
+    mem::size_of,
+};
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    errno::Errno,
+    fcntl::{open, OFlag},
+    libc::getrandom,
+    request_code_write,
+    sys::stat::{fchmod, fstat, Mode, SFlag},
+    unistd::{close, fsync, mkdir, unlink, write},
+};
+
+use crate::{rand_seed::SEED, utils::read_up_to};
+
+/// Where the random seed is kept across reboots
+static SEED_DIRECTORY: &CStr = cstr!("/var/lib/incipio");
+static SEED_PATH: &CStr = cstr!("/var/lib/incipio/random-seed");
+
+/// The size of the seed, which matches the size of the kernel's
+/// input pool
+const SEED_SIZE: usize = 512;
+
+/// `struct rand_pool_info` from `<linux/random.h>`, with room for a
+/// whole seed
+#[repr(C)]
+struct RandPoolInfo {
+    entropy_count: c_int,
+    buf_size: c_int,
+    buf: [u8; SEED_SIZE],
+}
+
+// RNDADDENTROPY is defined as `_IOW('R', 0x03, int [2])`
+nix::ioctl_write_ptr_bad!(
+    add_entropy,
+    request_code_write!(b'R', 0x03, size_of::<[c_int; 2]>()),
+    RandPoolInfo
+);
+
+/// Feeds the seed saved by the previous boot into the kernel's
+/// entropy pool, then replaces it with a fresh one so that the same
+/// seed is never used twice.
+///
+/// When there's no seed saved yet, the seed built into incipio is
+/// used instead, which is better than nothing but not credited as
+/// entropy since every copy of incipio shares it.
+pub fn load_random_seed() {
+    match load_saved_seed() {
+        Ok(()) => return,
+        Err(Errno::ENOENT) => {
+            if let Err(errno) = add_seed(SEED, false) {
+                libc_eprintln!(
+                    "Failed to use the built-in seed: {}",
+                    errno
+                );
+            }
+        }
+        Err(errno) => {
+            libc_eprintln!(
+                "Failed to load {:?}: {}",
+                SEED_PATH,
+                errno
+            )
+        }
+    }
+
+    save_random_seed();
+}
+
+/// Saves a fresh seed for the next boot to use.
+pub fn save_random_seed() {
+    if let Err(errno) = save_seed() {
+        libc_eprintln!("Failed to save {:?}: {}", SEED_PATH, errno);
+    }
+}
+
+/// Mixes in the saved seed and replaces it, only crediting it as
+/// entropy if it could not be read again on the next boot
+fn load_saved_seed() -> nix::Result<()> {
+    let fd = open(
+        SEED_PATH,
+        OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    let mut seed = [0; SEED_SIZE];
+    let result = fstat(fd).and_then(|status| {
+        let len = read_up_to(fd, &mut seed)?;
+
+        // Only a seed nobody else could have read or written is as
+        // random as it looks
+        let is_regular = status.st_mode & SFlag::S_IFMT.bits()
+            == SFlag::S_IFREG.bits();
+        let is_private = status.st_mode & 0o077 == 0;
+        let is_trusted =
+            is_regular && status.st_uid == 0 && is_private;
+
+        Ok((len, is_trusted))
+    });
+
+    let _ = close(fd);
+
+    let (len, is_trusted) = result?;
+    let seed = &seed[..len];
+
+    // Mixed in before saving, so that the next seed depends on it
+    // even if the pool is not initialized yet
+    add_seed(seed, false)?;
+
+    // Crediting the same seed on every boot, e.g. from a read-only
+    // root, would overestimate the entropy of the pool
+    let is_used_up = match save_seed() {
+        Ok(()) => true,
+        Err(errno) => {
+            libc_eprintln!(
+                "Failed to save {:?}: {}",
+                SEED_PATH,
+                errno
+            );
+            unlink(SEED_PATH).is_ok()
+        }
+    };
+
+    if is_trusted && is_used_up {
+        add_seed(seed, true)?;
+    }
+
+    Ok(())
+}
+
+/// Mixes `seed` into the kernel's entropy pool, crediting it as
+/// entropy if `credit` is set.
+fn add_seed(seed: &[u8], credit: bool) -> nix::Result<()> {
+    let len = seed.len().min(SEED_SIZE);
+    let mut info = RandPoolInfo {
+        entropy_count: 0,
+        buf_size: len as c_int,
+        buf: [0; SEED_SIZE],
+    };
+    info.buf[..len].copy_from_slice(&seed[..len]);
+
+    if credit {
+        // Counted in bits
+        info.entropy_count = (len * 8) as c_int;
+    }
+
+    let fd = open(
+        cstr!("/dev/urandom"),
+        OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    // Safety: `info` is a valid `rand_pool_info` with `buf_size`
+    // bytes of data following its header
+    let result = unsafe { add_entropy(fd, &info) };
+    let _ = close(fd);
+
+    result.map(drop)
+}
+
+fn save_seed() -> nix::Result<()> {
+    let mut seed = [0; SEED_SIZE];
+
+    // Don't block if the pool is not initialized yet: we'd rather
+    // keep the previous seed than hang the boot
+    let flags: c_uint = nix::libc::GRND_NONBLOCK;
+    let ret_val = unsafe {
+        getrandom(seed.as_mut_ptr().cast(), seed.len(), flags)
+    };
+    if Errno::result(ret_val)? as usize != seed.len() {
+        return Err(Errno::EIO);
+    }
+
+    match mkdir(SEED_DIRECTORY, Mode::S_IRWXU) {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(errno) => return Err(errno),
+    }
+
+    let fd = open(
+        SEED_PATH,
+        OFlag::O_WRONLY
+            | OFlag::O_CREAT
+            | OFlag::O_TRUNC
+            | OFlag::O_NOFOLLOW
+            | OFlag::O_CLOEXEC,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    )?;
+
+    let result = (|| {
+        // The file may predate us with looser permissions, which
+        // would keep it from being trusted on the next boot
+        fchmod(fd, Mode::S_IRUSR | Mode::S_IWUSR)?;
+
+        let mut written = 0;
+        while written < seed.len() {
+            match write(fd, &seed[written..]) {
+                Ok(len) => written += len,
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(errno),
+            }
+        }
+
+        fsync(fd)
+    })();
+
+    let _ = close(fd);
+
+    result
+}
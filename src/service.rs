@@ -0,0 +1,335 @@
+use core::ffi::CStr;
+
+use cstr::cstr;
+use libc_print::libc_eprintln;
+use nix::{
+    dir::{Dir, Type},
+    errno::Errno,
+    fcntl::OFlag,
+    sys::stat::Mode,
+    unistd::Pid,
+};
+
+use crate::{
+    exec::command::Command,
+    fixed::{CStrList, FixedCStr},
+    parser::{KeyValues, Words},
+    supervisor::{RestartPolicy, Supervisor},
+    target::Target,
+    utils::FileMapping,
+    Error,
+};
+
+/// The directory where service definitions are read from, one
+/// service per file, named after the service.
+///
+/// A service definition looks like:
+///
+/// ```text
+/// # The program to run. Required.
+/// command = /usr/bin/sshd
+/// # Arguments given to the program. May be repeated.
+/// arguments = -D -e
+/// # Environment variables. May be repeated. If not given at all,
+/// # the service inherits the environment of incipio.
+/// environment = LANG=C.UTF-8 "MOTD=Hello there"
+/// # A user name, UID or uid:gid pair. Defaults to root.
+/// user = sshd
+/// # One of `always`, `on-failure` (the default) or `never`.
+/// restart = always
+/// # `simple` (the default) services are considered up as soon as
+/// # they're started, while `oneshot` ones are only considered done
+/// # once they exit successfully, and so can't be restarted
+/// # `always`.
+/// type = simple
+/// # Services this one can't run without, which are started before
+/// # it. May be repeated.
+/// requires = network
+/// # Services that, if defined, must be started before this one,
+/// # but which are not required by it. May be repeated.
+/// after = syslog
+/// # The first target the service is part of, which makes it part
+/// # of the targets after it too: `rescue`, `multi-user` (the
+/// # default) or `graphical`. The services it requires are run
+/// # in those targets as well.
+/// target = graphical
+/// # A terminal the service runs on, as its controlling terminal
+/// # and standard streams. By default, those of incipio are used.
+/// tty = /dev/tty9
+/// # The working directory. Defaults to `/`.
+/// directory = /var/empty
+/// # The file mode creation mask, in octal. Defaults to incipio's.
+/// umask = 027
+/// # The scheduling priority, from -20 (highest) to 19 (lowest).
+/// nice = 5
+/// ```
+///
+/// Services with no dependencies between them are started in
+/// parallel, each in a session of its own.
+pub static SERVICES_DIRECTORY: &CStr = cstr!("/etc/incipio/services");
+
+/// The name of a service, which is the name of its definition file
+pub type ServiceName = FixedCStr<32>;
+
+/// The names of the services another service depends on
+pub type Dependencies = CStrList<256>;
+
+/// When a service is considered up, so that services depending on
+/// it can be started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceKind {
+    /// Up as soon as it's started, e.g. a daemon
+    Simple,
+    /// Up once it exits successfully, e.g. a setup script
+    Oneshot,
+}
+
+/// A process incipio starts and keeps supervised
+#[derive(Clone, Copy)]
+pub struct Service {
+    pub name: ServiceName,
+    /// The program to run and everything about how it's run
+    pub command: Command,
+    pub restart: RestartPolicy,
+    pub kind: ServiceKind,
+    /// The services that must be up before this one starts
+    pub requires: Dependencies,
+    /// The services that, if wanted, must be up before this one
+    /// starts
+    pub after: Dependencies,
+    /// The first target the service is part of
+    pub target: Target,
+}
+
+impl Service {
+    /// A service running `command` in a session of its own
+    pub fn new(
+        name: ServiceName,
+        mut command: Command,
+        restart: RestartPolicy,
+    ) -> Self {
+        command.new_session();
+
+        Self {
+            name,
+            command,
+            restart,
+            kind: ServiceKind::Simple,
+            requires: Dependencies::new(),
+            after: Dependencies::new(),
+            target: Target::MultiUser,
+        }
+    }
+
+    /// Parses the service definition `contents` of the service
+    /// called `name`.
+    pub fn parse(
+        name: &[u8],
+        contents: &[u8],
+    ) -> crate::Result<Self> {
+        let name = ServiceName::from_bytes(name)?;
+
+        // The command must come first in `argv` but may appear
+        // after the arguments in the file
+        let program = KeyValues::new(contents)
+            .flatten()
+            .filter(|(key, _)| *key == b"command")
+            .map(|(_, value)| value)
+            .last()
+            .ok_or(Error::MissingCommand)?;
+        let mut command = Command::new(program)?;
+        // Rather than whatever directory incipio happens to be in
+        command.current_dir(b"/")?;
+
+        let mut service =
+            Service::new(name, command, RestartPolicy::OnFailure);
+
+        let mut pairs = KeyValues::new(contents);
+
+        while let Some(pair) = pairs.next() {
+            let result = pair.and_then(|(key, value)| match key {
+                b"command" => Ok(()),
+                b"arguments" => {
+                    Words::new(value).try_for_each(|word| {
+                        service.command.arg(word).map(drop)
+                    })
+                }
+                b"environment" => {
+                    Words::new(value).try_for_each(|variable| {
+                        if !variable.contains(&b'=') {
+                            return Err(Error::InvalidConfigValue);
+                        }
+                        service.command.env(variable).map(drop)
+                    })
+                }
+                b"user" => service.command.user(value).map(drop),
+                b"restart" => {
+                    service.restart = parse_restart_policy(value)?;
+                    Ok(())
+                }
+                b"type" => {
+                    service.kind = parse_service_kind(value)?;
+                    Ok(())
+                }
+                b"requires" => Words::new(value)
+                    .try_for_each(|name| service.requires.push(name)),
+                b"after" => Words::new(value)
+                    .try_for_each(|name| service.after.push(name)),
+                b"target" => {
+                    service.target = Target::from_name(value)
+                        .ok_or(Error::InvalidConfigValue)?;
+                    Ok(())
+                }
+                b"tty" => service.command.tty(value).map(drop),
+                b"directory" => {
+                    service.command.current_dir(value).map(drop)
+                }
+                b"umask" => {
+                    service.command.umask(parse_umask(value)?);
+                    Ok(())
+                }
+                b"nice" => {
+                    service.command.nice(parse_nice(value)?);
+                    Ok(())
+                }
+                _ => Err(Error::UnknownConfigKey),
+            });
+
+            if let Err(err) = result {
+                libc_eprintln!(
+                    "{:?}, line {}: {}",
+                    service.name.as_cstr(),
+                    pairs.line_number(),
+                    err.description()
+                );
+                return Err(err);
+            }
+        }
+
+        // It would never finish, so whatever depends on it would
+        // wait forever
+        if service.kind == ServiceKind::Oneshot
+            && service.restart == RestartPolicy::Always
+        {
+            let err = Error::AlwaysRestartedOneshot;
+            libc_eprintln!(
+                "{:?}: {}",
+                service.name.as_cstr(),
+                err.description()
+            );
+            return Err(err);
+        }
+
+        Ok(service)
+    }
+
+    /// Forks and executes this service, returning its PID.
+    pub fn spawn(&self) -> crate::Result<Pid> {
+        self.command.spawn()
+    }
+}
+
+fn parse_restart_policy(
+    value: &[u8],
+) -> crate::Result<RestartPolicy> {
+    match value {
+        b"always" => Ok(RestartPolicy::Always),
+        b"on-failure" => Ok(RestartPolicy::OnFailure),
+        b"never" => Ok(RestartPolicy::Never),
+        _ => Err(Error::InvalidConfigValue),
+    }
+}
+
+fn parse_service_kind(value: &[u8]) -> crate::Result<ServiceKind> {
+    match value {
+        b"simple" => Ok(ServiceKind::Simple),
+        b"oneshot" => Ok(ServiceKind::Oneshot),
+        _ => Err(Error::InvalidConfigValue),
+    }
+}
+
+/// Parses an octal umask such as `027`
+fn parse_umask(value: &[u8]) -> crate::Result<Mode> {
+    core::str::from_utf8(value)
+        .ok()
+        .and_then(|value| u32::from_str_radix(value, 8).ok())
+        .and_then(Mode::from_bits)
+        .ok_or(Error::InvalidConfigValue)
+}
+
+/// Parses a niceness, which goes from -20 to 19
+fn parse_nice(value: &[u8]) -> crate::Result<i32> {
+    core::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|nice| (-20..=19).contains(nice))
+        .ok_or(Error::InvalidConfigValue)
+}
+
+/// Reads the service definition of the service called `name`
+fn load_service(name: &[u8]) -> crate::Result<Service> {
+    let mut path =
+        FixedCStr::<256>::from_bytes(SERVICES_DIRECTORY.to_bytes())?;
+    path.push(b"/")?;
+    path.push(name)?;
+
+    let mut mapping = FileMapping::open(path.as_cstr())?;
+    let service = Service::parse(name, mapping.as_slice());
+    mapping.close()?;
+
+    service
+}
+
+/// Adds every service defined in [`SERVICES_DIRECTORY`] to
+/// `supervisor`, without starting them.
+fn load_services(supervisor: &mut Supervisor) -> crate::Result<()> {
+    let mut directory = Dir::open(
+        SERVICES_DIRECTORY,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    for entry in directory.iter() {
+        let entry = entry?;
+        let name = entry.file_name().to_bytes();
+
+        // Skips `.`, `..` and hidden files such as editor backups
+        if name.starts_with(b".")
+            || entry.file_type() == Some(Type::Directory)
+        {
+            continue;
+        }
+
+        let result = load_service(name)
+            .and_then(|service| supervisor.add(service));
+
+        if let Err(err) = result {
+            libc_eprintln!(
+                "Failed to load service {:?}: {}",
+                entry.file_name(),
+                err.description()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the services defined in [`SERVICES_DIRECTORY`] and starts
+/// them under `supervisor`, in dependency order.
+pub fn start_services(supervisor: &mut Supervisor) {
+    match load_services(supervisor) {
+        Ok(()) => {}
+        // Having no services defined is fine
+        Err(Error::Errno(Errno::ENOENT)) => {}
+        Err(err) => {
+            libc_eprintln!(
+                "Failed to read {:?}: {}",
+                SERVICES_DIRECTORY,
+                err.description()
+            );
+        }
+    }
+
+    supervisor.start_all();
+}
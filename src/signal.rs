@@ -1,72 +1,196 @@
+use core::{ffi::c_int, mem::MaybeUninit};
+
 use nix::{
-    libc::{LINUX_REBOOT_CMD_POWER_OFF, LINUX_REBOOT_CMD_RESTART},
+    errno::Errno,
+    libc::signalfd_siginfo,
     sys::{
-        signal::{sigaction, SaFlags, SigAction, Signal},
-        signalfd::SigSet,
+        signal::{self, SigHandler, Signal},
+        signalfd::{signalfd, SfdFlags, SigSet},
     },
+    unistd::{close, read},
 };
 
-use crate::wait::reap_child_processes;
+use crate::power::PowerAction;
 
-type Callback = fn() -> i32;
+/// What incipio should do after receiving a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Reap the child processes that have exited
+    ReapChildren,
+    /// Stop the system, then power it off, reboot or halt it
+    Shutdown(PowerAction),
+    /// Stop everything and run a shell on the console
+    Rescue,
+}
 
-/// Wrapper for [`reap_child_processes`] that returns -1 instead
-/// of the amount of reaped processes.
+/// Which signals mean poweroff, reboot and halt.
 ///
-/// This is needed since `handle_signal` will reboot the system
-/// if the callback is larger than 0.
-fn reap_child_processes_wrapper() -> i32 {
-    reap_child_processes();
-    -1
+/// Other init systems don't agree on this, so tools written for
+/// them only work against incipio if it speaks their convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalProfile {
+    /// SIGUSR1 powers off, SIGINT reboots and SIGUSR2 halts
+    Incipio,
+    /// The convention of busybox init and runit's `init`: SIGUSR2
+    /// powers off, SIGTERM reboots and SIGUSR1 halts
+    Busybox,
 }
 
-fn restart_machine() -> i32 {
-    LINUX_REBOOT_CMD_RESTART
-}
+impl SignalProfile {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"incipio" => Some(Self::Incipio),
+            b"busybox" => Some(Self::Busybox),
+            _ => None,
+        }
+    }
 
-fn shutdown_machine() -> i32 {
-    LINUX_REBOOT_CMD_POWER_OFF
-}
+    /// The signals bringing the system down, along with what they
+    /// do. When two signals have the same action, the first one is
+    /// the one sent by `poweroff` and friends.
+    fn power_signals(self) -> &'static [(Signal, PowerAction)] {
+        match self {
+            Self::Incipio => &[
+                (Signal::SIGUSR1, PowerAction::PowerOff),
+                (Signal::SIGINT, PowerAction::Reboot),
+                (Signal::SIGUSR2, PowerAction::Halt),
+            ],
+            // SIGINT is what the kernel sends on Ctrl+Alt+Del
+            Self::Busybox => &[
+                (Signal::SIGUSR2, PowerAction::PowerOff),
+                (Signal::SIGTERM, PowerAction::Reboot),
+                (Signal::SIGUSR1, PowerAction::Halt),
+                (Signal::SIGINT, PowerAction::Reboot),
+            ],
+        }
+    }
 
-extern "C" fn handle_signal(received_signal: i32) {
-    if let Some(callback) = signal_to_action(received_signal) {
-        let ret_val = callback();
+    /// The signals incipio reacts to under this profile
+    fn handled_signals(self) -> SigSet {
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGCHLD);
+        for &(signal, _) in self.power_signals() {
+            signals.add(signal);
+        }
 
-        if ret_val < 0 {
-            return;
+        signals
+    }
+
+    /// What to do after receiving `signal`
+    pub fn action(self, signal: c_int) -> Option<Action> {
+        let signal = Signal::try_from(signal).ok()?;
+        if signal == Signal::SIGCHLD {
+            return Some(Action::ReapChildren);
         }
+
+        self.power_signals()
+            .iter()
+            .find(|&&(handled, _)| handled == signal)
+            .map(|&(_, action)| Action::Shutdown(action))
     }
 
-    // TODO: shutdown and reboot the system
+    /// The signal that asks PID 1 to carry out `action`
+    pub fn signal(self, action: PowerAction) -> Signal {
+        self.power_signals()
+            .iter()
+            .find(|&&(_, handled)| handled == action)
+            .map(|&(signal, _)| signal)
+            // Every profile has a signal for every action
+            .unwrap_or(Signal::SIGUSR1)
+    }
+}
+
+/// A `signalfd` through which the signals incipio handles are
+/// received.
+///
+/// Since no signal handler ever runs, every action we take in
+/// response to a signal happens synchronously in the main loop,
+/// where it's safe to log, allocate file descriptors and so on.
+pub struct SignalReceiver {
+    raw_fd: c_int,
+    profile: SignalProfile,
+}
+
+impl Drop for SignalReceiver {
+    fn drop(&mut self) {
+        let _ = close(self.raw_fd);
+    }
 }
 
-/// Installs the global signal handler for this process
-pub fn install_signal_handler() -> nix::Result<()> {
-    // Handle signals with the `handle_signal` function.
-    let handler =
-        nix::sys::signal::SigHandler::Handler(handle_signal);
-
-    // The action to take whenever we receive a signal interrupt
-    let sig_action = SigAction::new(
-        // Send signals to the `handle_signal` function
-        handler,
-        SaFlags::empty(),
-        // sigemptyset
-        SigSet::empty(),
-    );
-
-    for signal in [Signal::SIGINT, Signal::SIGUSR1, Signal::SIGCHLD] {
-        unsafe { sigaction(signal, &sig_action)? };
+impl SignalReceiver {
+    pub fn raw_fd(&self) -> c_int {
+        self.raw_fd
     }
 
-    Ok(())
+    /// The profile the signals received are interpreted with
+    pub fn profile(&self) -> SignalProfile {
+        self.profile
+    }
+
+    /// Reads the number of the next pending signal, blocking until
+    /// one arrives.
+    pub fn read_signal(&self) -> nix::Result<c_int> {
+        let mut info = MaybeUninit::<signalfd_siginfo>::zeroed();
+
+        // Safety: `signalfd_siginfo` is plain old data, so any bytes
+        // are a valid value for it
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(
+                info.as_mut_ptr() as *mut u8,
+                core::mem::size_of::<signalfd_siginfo>(),
+            )
+        };
+
+        // Reads from a signalfd are never partial
+        if read(self.raw_fd, buffer)? != buffer.len() {
+            return Err(Errno::EIO);
+        }
+
+        let info = unsafe { info.assume_init() };
+
+        Ok(info.ssi_signo as c_int)
+    }
 }
 
-pub fn signal_to_action(signal: i32) -> Option<Callback> {
-    match signal {
-        nix::libc::SIGCHLD => Some(reap_child_processes_wrapper),
-        nix::libc::SIGUSR1 => Some(shutdown_machine),
-        nix::libc::SIGINT => Some(restart_machine),
-        _ => None,
+/// Blocks the signals incipio handles under `profile` and returns a
+/// `signalfd` through which they'll be delivered instead.
+pub fn block_handled_signals(
+    profile: SignalProfile,
+) -> nix::Result<SignalReceiver> {
+    let signals = profile.handled_signals();
+
+    // Must happen before the signalfd is created, otherwise the
+    // signals could still get delivered through their default
+    // dispositions
+    signals.thread_block()?;
+
+    // -1 asks for a new signalfd to be created
+    let raw_fd = signalfd(-1, &signals, SfdFlags::SFD_CLOEXEC)?;
+
+    Ok(SignalReceiver { raw_fd, profile })
+}
+
+/// Unblocks every signal for the calling process.
+///
+/// The signal mask is inherited through `fork` and `execve`, so
+/// children of incipio must call this before running anything else.
+pub fn unblock_all_signals() -> nix::Result<()> {
+    SigSet::empty().thread_set_mask()
+}
+
+/// Restores the default disposition of every signal that has one
+/// which can be changed.
+///
+/// Ignored signals stay ignored through `execve`, so children of
+/// incipio call this for programs to get the dispositions they
+/// expect.
+pub fn reset_signal_dispositions() {
+    for signal in Signal::iterator() {
+        if matches!(signal, Signal::SIGKILL | Signal::SIGSTOP) {
+            continue;
+        }
+
+        // Safety: no handler is being installed
+        let _ = unsafe { signal::signal(signal, SigHandler::SigDfl) };
     }
 }
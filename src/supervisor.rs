@@ -0,0 +1,848 @@
+use core::{ffi::CStr, fmt, time::Duration};
+
+use libc_print::libc_eprintln;
+use nix::{
+    sys::{
+        signal::{kill, Signal},
+        wait::WaitStatus,
+    },
+    unistd::Pid,
+};
+
+use crate::{
+    graph::{DependencyGraph, TopologicalOrder},
+    service::{Service, ServiceKind, ServiceName},
+    target::Target,
+    utils::monotonic_time,
+    wait::reap_child_processes,
+    Error,
+};
+
+/// The maximum amount of processes incipio is able to supervise
+pub const MAX_SUPERVISED: usize = 48;
+
+/// Processes that stay up for at least this long are considered to
+/// have started successfully, which resets their backoff.
+const STABLE_UPTIME: Duration = Duration::from_secs(10);
+
+/// The longest we'll wait before respawning a crash-looping process
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a service has to exit after being asked to stop, before
+/// it gets SIGKILLed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do once a supervised process exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Always respawn the process
+    Always,
+    /// Only respawn the process if it did not exit with status 0
+    OnFailure,
+    /// Never respawn the process
+    Never,
+}
+
+impl RestartPolicy {
+    fn should_restart(self, status: WaitStatus) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => {
+                !matches!(status, WaitStatus::Exited(_, 0))
+            }
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
+/// The lifecycle of a supervised service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Not running and not going to be started by itself
+    Stopped,
+    /// Waiting for its dependencies to be up before starting
+    Waiting,
+    /// Running with the given PID
+    Running(Pid),
+    /// Running with the given PID, but left out of the current
+    /// target, so it gets stopped once the services depending on it
+    /// have stopped
+    PendingStop(Pid),
+    /// Exited and due to be started again at the given time
+    Respawning(Duration),
+    /// Asked to stop, waiting for the process to exit. It gets
+    /// SIGKILLed past `deadline` and, if `restart` is set, started
+    /// again after exiting.
+    Stopping {
+        pid: Pid,
+        deadline: Duration,
+        restart: bool,
+    },
+    /// Exited successfully and won't be started again
+    Finished,
+    /// Exited unsuccessfully or could not be started at all, and
+    /// won't be started again
+    Failed,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Stopped => f.write_str("stopped"),
+            State::Waiting => f.write_str("waiting"),
+            State::Running(pid) => write!(f, "running (PID {pid})"),
+            State::PendingStop(pid) => {
+                write!(f, "waiting to stop (PID {pid})")
+            }
+            State::Respawning(_) => f.write_str("respawning"),
+            State::Stopping { pid, .. } => {
+                write!(f, "stopping (PID {pid})")
+            }
+            State::Finished => f.write_str("finished"),
+            State::Failed => f.write_str("failed"),
+        }
+    }
+}
+
+/// Whether a service waiting to start may do so
+enum Readiness {
+    /// Every dependency is up
+    Ready,
+    /// Some dependency is not up yet
+    Pending,
+    /// The given dependency will never be up, for the given reason
+    Blocked(ServiceName, &'static str),
+}
+
+struct Supervised {
+    service: Service,
+    state: State,
+    /// When the process was last started
+    started_at: Duration,
+    /// How many times in a row the process exited too early
+    failures: u32,
+}
+
+impl Supervised {
+    fn name(&self) -> &CStr {
+        self.service.name.as_cstr()
+    }
+
+    fn is_part_of(&self, target: Target) -> bool {
+        target.includes(self.service.target)
+    }
+
+    /// Whether the process is running, even if on its way out
+    fn is_alive(&self) -> bool {
+        matches!(
+            self.state,
+            State::Running(_)
+                | State::PendingStop(_)
+                | State::Stopping { .. }
+        )
+    }
+
+    /// Whether services depending on this one may start
+    fn is_up(&self) -> bool {
+        matches!(
+            (self.service.kind, self.state),
+            (_, State::Finished)
+                | (ServiceKind::Simple, State::Running(_))
+                | (ServiceKind::Simple, State::PendingStop(_))
+        )
+    }
+
+    fn start(&mut self, now: Duration) -> crate::Result<()> {
+        self.started_at = now;
+        let pid = self.service.spawn()?;
+        self.state = State::Running(pid);
+        crate::debug!("Started {:?} as PID {}", self.name(), pid);
+
+        Ok(())
+    }
+
+    /// Asks the process to exit through SIGTERM, if it's running.
+    ///
+    /// Returns false if there was no process to stop.
+    fn stop(&mut self, now: Duration, restart: bool) -> bool {
+        let pid = match self.state {
+            State::Running(pid) | State::PendingStop(pid) => pid,
+            State::Stopping { pid, .. } => pid,
+            _ => return false,
+        };
+
+        if let Err(errno) = kill(pid, Signal::SIGTERM) {
+            libc_eprintln!(
+                "Failed to stop {:?}: {}",
+                self.name(),
+                errno
+            );
+        }
+
+        self.state = State::Stopping {
+            pid,
+            deadline: now + STOP_TIMEOUT,
+            restart,
+        };
+
+        true
+    }
+
+    /// Schedules the process to be started again, backing off
+    /// exponentially if it keeps exiting right after starting.
+    fn schedule_respawn(&mut self, now: Duration) {
+        let uptime = now.saturating_sub(self.started_at);
+
+        let delay = if uptime >= STABLE_UPTIME {
+            self.failures = 0;
+            Duration::ZERO
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            // 1s, 2s, 4s, ... up to `MAX_BACKOFF`
+            let secs = 1_u64
+                .checked_shl(self.failures - 1)
+                .unwrap_or(u64::MAX);
+            Duration::from_secs(secs).min(MAX_BACKOFF)
+        };
+
+        self.state = State::Respawning(now + delay);
+    }
+}
+
+/// Displays the names of the processes in a bit set of slots, e.g.
+/// the nodes of a dependency cycle
+struct ServiceNames<'a> {
+    processes: &'a [Option<Supervised>],
+    set: u64,
+}
+
+impl fmt::Display for ServiceNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.set & (1 << idx) != 0)
+            .filter_map(|(_, slot)| slot.as_ref());
+
+        for (nth, process) in names.enumerate() {
+            if nth > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:?}", process.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A fixed-capacity table of the processes incipio keeps running.
+pub struct Supervisor {
+    processes: [Option<Supervised>; MAX_SUPERVISED],
+    /// Which of the processes are meant to be running
+    target: Target,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new(Target::Graphical)
+    }
+}
+
+impl Supervisor {
+    pub const fn new(target: Target) -> Self {
+        const EMPTY: Option<Supervised> = None;
+
+        Self {
+            processes: [EMPTY; MAX_SUPERVISED],
+            target,
+        }
+    }
+
+    fn find(&self, name: &[u8]) -> Option<&Supervised> {
+        self.processes
+            .iter()
+            .flatten()
+            .find(|process| process.service.name.as_bytes() == name)
+    }
+
+    fn find_mut(
+        &mut self,
+        name: &[u8],
+    ) -> crate::Result<&mut Supervised> {
+        self.processes
+            .iter_mut()
+            .flatten()
+            .find(|process| process.service.name.as_bytes() == name)
+            .ok_or(Error::UnknownService)
+    }
+
+    /// The index of the slot of the service called `name`
+    fn position(&self, name: &CStr) -> Option<usize> {
+        self.processes.iter().position(|slot| {
+            slot.as_ref().is_some_and(|process| {
+                process.service.name.as_bytes() == name.to_bytes()
+            })
+        })
+    }
+
+    /// The slots of the services meant to run in the current target,
+    /// as a bit set: the ones part of it, along with whatever they
+    /// require, even if that's only part of a later target
+    fn wanted(&self) -> u64 {
+        let mut wanted = self
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| {
+                slot.as_ref().is_some_and(|process| {
+                    process.is_part_of(self.target)
+                })
+            })
+            .fold(0, |wanted, (idx, _)| wanted | 1 << idx);
+
+        // Each pass adds the requirements of what the previous one
+        // added
+        loop {
+            let mut required = wanted;
+            for (idx, slot) in self.processes.iter().enumerate() {
+                let Some(process) =
+                    slot.as_ref().filter(|_| wanted & 1 << idx != 0)
+                else {
+                    continue;
+                };
+
+                for name in process.service.requires.iter() {
+                    if let Some(dependency) = self.position(name) {
+                        required |= 1 << dependency;
+                    }
+                }
+            }
+
+            if required == wanted {
+                return wanted;
+            }
+            wanted = required;
+        }
+    }
+
+    /// Builds the graph of the dependencies between the services
+    fn dependency_graph(&self) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+
+        for (idx, slot) in self.processes.iter().enumerate() {
+            let Some(process) = slot else {
+                continue;
+            };
+
+            graph.add_node(idx);
+
+            let service = &process.service;
+            for dependency in
+                service.requires.iter().chain(service.after.iter())
+            {
+                if let Some(dependency) = self.position(dependency) {
+                    graph.add_edge(dependency, idx);
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Checks whether the dependencies of `process` are up
+    fn readiness(&self, process: &Supervised) -> Readiness {
+        for name in process.service.requires.iter() {
+            let blocked = |reason| {
+                // Names longer than a `ServiceName` could never have
+                // been defined in the first place
+                let name = ServiceName::from_bytes(name.to_bytes())
+                    .unwrap_or_default();
+                Readiness::Blocked(name, reason)
+            };
+
+            let Some(dependency) = self.find(name.to_bytes()) else {
+                return blocked("is not defined");
+            };
+
+            match dependency.state {
+                _ if dependency.is_up() => {}
+                State::Stopped => return blocked("is not started"),
+                State::Failed => return blocked("failed"),
+                _ => return Readiness::Pending,
+            }
+        }
+
+        for name in process.service.after.iter() {
+            // Ordering-only dependencies that won't ever be up are
+            // simply ignored
+            let pending = self.find(name.to_bytes()).is_some_and(
+                |dependency| {
+                    !dependency.is_up()
+                        && !matches!(
+                            dependency.state,
+                            State::Stopped | State::Failed
+                        )
+                },
+            );
+
+            if pending {
+                return Readiness::Pending;
+            }
+        }
+
+        Readiness::Ready
+    }
+
+    /// Adds `service` to the table, returning the index of its slot
+    fn insert(&mut self, service: Service) -> crate::Result<usize> {
+        if self.find(service.name.as_bytes()).is_some() {
+            return Err(Error::DuplicateService);
+        }
+
+        let idx = self
+            .processes
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::CapacityExceeded)?;
+
+        self.processes[idx] = Some(Supervised {
+            service,
+            state: State::Stopped,
+            started_at: Duration::ZERO,
+            failures: 0,
+        });
+
+        Ok(idx)
+    }
+
+    /// Adds `service` to the table, without starting it.
+    pub fn add(&mut self, service: Service) -> crate::Result<()> {
+        self.insert(service).map(drop)
+    }
+
+    /// Starts `service` right away, respawning it according to its
+    /// restart policy whenever it exits. Services not wanted by the
+    /// current target are only added to the table.
+    pub fn supervise(
+        &mut self,
+        service: Service,
+    ) -> crate::Result<()> {
+        let idx = self.insert(service)?;
+        let is_wanted = self.wanted() & 1 << idx != 0;
+        let slot = &mut self.processes[idx];
+
+        if let Some(process) = slot.as_mut().filter(|_| is_wanted) {
+            if let Err(err) = process.start(monotonic_time()) {
+                // Only hold on to processes that got started at least
+                // once
+                *slot = None;
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts every stopped service wanted by the current target, in
+    /// dependency order.
+    ///
+    /// Services are started as soon as their dependencies are up,
+    /// so independent services all get started right away while
+    /// the others are started by [`Supervisor::reap`] once what
+    /// they depend on has finished.
+    pub fn start_all(&mut self) {
+        let wanted = self.wanted();
+        for (idx, slot) in self.processes.iter_mut().enumerate() {
+            let Some(process) = slot else {
+                continue;
+            };
+
+            if process.state == State::Stopped
+                && wanted & 1 << idx != 0
+            {
+                process.state = State::Waiting;
+            }
+        }
+
+        let graph = self.dependency_graph();
+        let order = graph.topological_order();
+
+        for idx in 0..MAX_SUPERVISED {
+            let Some(process) = &self.processes[idx] else {
+                continue;
+            };
+
+            if process.state == State::Waiting && !order.contains(idx)
+            {
+                libc_eprintln!(
+                    "Not starting {:?}: {}: {}",
+                    process.name(),
+                    Error::DependencyCycle.description(),
+                    ServiceNames {
+                        processes: &self.processes,
+                        set: graph.cycle_behind(idx, &order),
+                    }
+                );
+
+                if let Some(process) = &mut self.processes[idx] {
+                    process.state = State::Failed;
+                }
+            }
+        }
+
+        self.start_ready(&order);
+    }
+
+    /// Starts the waiting services whose dependencies are up.
+    fn start_ready(&mut self, order: &TopologicalOrder) {
+        let now = monotonic_time();
+
+        // Going in dependency order means that a whole chain of
+        // simple services gets started in a single pass
+        for &idx in order.as_slice() {
+            let Some(process) = &self.processes[idx] else {
+                continue;
+            };
+
+            if process.state != State::Waiting {
+                continue;
+            }
+
+            let readiness = self.readiness(process);
+
+            let Some(process) = &mut self.processes[idx] else {
+                continue;
+            };
+
+            match readiness {
+                Readiness::Ready => {
+                    if let Err(err) = process.start(now) {
+                        libc_eprintln!(
+                            "Failed to start {:?}: {}",
+                            process.name(),
+                            err.description()
+                        );
+                        process.state = State::Failed;
+                    }
+                }
+                Readiness::Pending => {}
+                Readiness::Blocked(dependency, reason) => {
+                    libc_eprintln!(
+                        "Not starting {:?}: {:?} {}",
+                        process.name(),
+                        dependency.as_cstr(),
+                        reason
+                    );
+                    process.state = State::Failed;
+                }
+            }
+        }
+    }
+
+    /// The target the supervised processes are running for
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Switches to `target`, stopping the services it doesn't want,
+    /// dependents first, and starting the ones it does.
+    ///
+    /// Services that finished or failed are left alone, as they
+    /// would be by [`Supervisor::start_all`].
+    pub fn switch_target(&mut self, target: Target) {
+        self.target = target;
+        let wanted = self.wanted();
+
+        for (idx, slot) in self.processes.iter_mut().enumerate() {
+            let Some(process) = slot else {
+                continue;
+            };
+
+            if wanted & 1 << idx != 0 {
+                // Cancels a stop from a previous switch
+                if let State::PendingStop(pid) = process.state {
+                    process.state = State::Running(pid);
+                }
+                continue;
+            }
+
+            process.state = match process.state {
+                State::Running(pid) => State::PendingStop(pid),
+                State::Waiting | State::Respawning(_) => {
+                    State::Stopped
+                }
+                State::Stopping { pid, deadline, .. } => {
+                    State::Stopping {
+                        pid,
+                        deadline,
+                        restart: false,
+                    }
+                }
+                state => state,
+            };
+        }
+
+        let graph = self.dependency_graph();
+        self.stop_ready(&graph, &graph.topological_order());
+        self.start_all();
+    }
+
+    /// Stops the processes left out of the target once no process
+    /// depending on them is alive, other than the ones the target
+    /// keeps running after them.
+    fn stop_ready(
+        &mut self,
+        graph: &DependencyGraph,
+        order: &TopologicalOrder,
+    ) {
+        let now = monotonic_time();
+        let wanted = self.wanted();
+
+        for idx in 0..MAX_SUPERVISED {
+            let Some(process) = &self.processes[idx] else {
+                continue;
+            };
+
+            if !matches!(process.state, State::PendingStop(_)) {
+                continue;
+            }
+
+            // Processes stuck in a dependency cycle have no order
+            // to be stopped in
+            let has_live_dependents = order.contains(idx)
+                && graph.dependents(idx).any(|dependent| {
+                    wanted & 1 << dependent == 0
+                        && self.processes[dependent]
+                            .as_ref()
+                            .is_some_and(Supervised::is_alive)
+                });
+
+            if let Some(process) = &mut self.processes[idx] {
+                if !has_live_dependents {
+                    process.stop(now, false);
+                }
+            }
+        }
+    }
+
+    /// Stops every process and clears its backoff, without
+    /// signalling anything, for when they've all been killed some
+    /// other way and reaped without us knowing.
+    pub fn forget_all(&mut self) {
+        for process in self.processes.iter_mut().flatten() {
+            process.state = State::Stopped;
+            process.failures = 0;
+        }
+    }
+
+    /// Reaps every child process that exited, scheduling the
+    /// supervised ones to be respawned, and then starts the services
+    /// that were waiting on them.
+    pub fn reap(&mut self) {
+        let now = monotonic_time();
+
+        reap_child_processes(|status| self.handle_exit(status, now));
+
+        let graph = self.dependency_graph();
+        let order = graph.topological_order();
+        self.stop_ready(&graph, &order);
+        self.start_ready(&order);
+    }
+
+    fn handle_exit(&mut self, status: WaitStatus, now: Duration) {
+        let Some(pid) = status.pid() else {
+            return;
+        };
+
+        let Some(process) =
+            self.processes.iter_mut().flatten().find(|process| {
+                match process.state {
+                    State::Running(running)
+                    | State::PendingStop(running) => running == pid,
+                    State::Stopping { pid: stopping, .. } => {
+                        stopping == pid
+                    }
+                    _ => false,
+                }
+            })
+        else {
+            // Not a supervised process
+            return;
+        };
+
+        match process.state {
+            // Stopped on purpose, so the restart policy doesn't apply
+            State::Stopping { restart, .. } => {
+                process.state = if restart {
+                    State::Waiting
+                } else {
+                    State::Stopped
+                };
+                return;
+            }
+            // Was going to be stopped anyway
+            State::PendingStop(_) => {
+                process.state = State::Stopped;
+                return;
+            }
+            _ => {}
+        }
+
+        process.state = if matches!(status, WaitStatus::Exited(_, 0))
+        {
+            State::Finished
+        } else {
+            State::Failed
+        };
+
+        if process.service.restart.should_restart(status) {
+            process.schedule_respawn(now);
+        }
+    }
+
+    /// Starts every process whose respawn time has come and kills
+    /// the ones that are taking too long to stop.
+    pub fn handle_timeouts(&mut self) {
+        let now = monotonic_time();
+
+        for process in self.processes.iter_mut().flatten() {
+            match process.state {
+                State::Respawning(at) if at <= now => {
+                    if let Err(err) = process.start(now) {
+                        libc_eprintln!(
+                            "Failed to respawn {:?}: {}",
+                            process.name(),
+                            err.description()
+                        );
+                        process.schedule_respawn(now);
+                    }
+                }
+                State::Stopping {
+                    pid,
+                    deadline,
+                    restart,
+                } if deadline <= now => {
+                    libc_eprintln!(
+                        "{:?} did not stop in time, killing it",
+                        process.name()
+                    );
+                    let _ = kill(pid, Signal::SIGKILL);
+                    process.state = State::Stopping {
+                        pid,
+                        deadline: now + STOP_TIMEOUT,
+                        restart,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        // Services may have been waiting on the ones we respawned
+        let order = self.dependency_graph().topological_order();
+        self.start_ready(&order);
+    }
+
+    /// How long until the next respawn or stop timeout is due, if
+    /// there's any pending at all.
+    pub fn time_until_next_timeout(&self) -> Option<Duration> {
+        let now = monotonic_time();
+
+        self.processes
+            .iter()
+            .flatten()
+            .filter_map(|process| match process.state {
+                State::Respawning(at) => Some(at),
+                State::Stopping { deadline, .. } => Some(deadline),
+                _ => None,
+            })
+            .min()
+            .map(|at| at.saturating_sub(now))
+    }
+
+    /// Starts the service called `name`, once its dependencies are
+    /// up.
+    pub fn start_service(
+        &mut self,
+        name: &[u8],
+    ) -> crate::Result<()> {
+        let process = self.find_mut(name)?;
+
+        match process.state {
+            State::Stopped | State::Finished | State::Failed => {
+                process.failures = 0;
+                process.state = State::Waiting;
+            }
+            // Skip whatever is left of its backoff
+            State::Respawning(_) => process.state = State::Waiting,
+            State::Stopping { pid, deadline, .. } => {
+                process.state = State::Stopping {
+                    pid,
+                    deadline,
+                    restart: true,
+                };
+            }
+            // Keep it running after all
+            State::PendingStop(pid) => {
+                process.state = State::Running(pid);
+                return Ok(());
+            }
+            State::Waiting | State::Running(_) => {
+                return Err(Error::ServiceAlreadyRunning)
+            }
+        }
+
+        let order = self.dependency_graph().topological_order();
+        self.start_ready(&order);
+
+        Ok(())
+    }
+
+    /// Stops the service called `name`, which won't be respawned.
+    pub fn stop_service(&mut self, name: &[u8]) -> crate::Result<()> {
+        let now = monotonic_time();
+        let process = self.find_mut(name)?;
+
+        if let State::Waiting | State::Respawning(_) = process.state {
+            process.state = State::Stopped;
+            return Ok(());
+        }
+
+        if process.stop(now, false) {
+            Ok(())
+        } else {
+            Err(Error::ServiceNotRunning)
+        }
+    }
+
+    /// Stops the service called `name` if it's running, and then
+    /// starts it again.
+    pub fn restart_service(
+        &mut self,
+        name: &[u8],
+    ) -> crate::Result<()> {
+        let now = monotonic_time();
+
+        if self.find_mut(name)?.stop(now, true) {
+            Ok(())
+        } else {
+            self.start_service(name)
+        }
+    }
+
+    /// Writes the current target, followed by the name and state of
+    /// every service, one per line
+    pub fn write_status(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        writeln!(out, "target: {}", self.target.name())?;
+
+        for process in self.processes.iter().flatten() {
+            let name = process.name().to_str().unwrap_or("<invalid>");
+            writeln!(out, "{name:<24} {}", process.state)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+/// A set of services to run, chosen at boot through
+/// `default_target` in `/etc/incipio/incipio.conf` and switched
+/// between at runtime through `incipioctl target`.
+///
+/// Like sysvinit runlevels, each target includes the ones before
+/// it: services part of `multi-user` also run in `graphical`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    /// The bare minimum to repair the system, without gettys
+    Rescue,
+    /// Gettys and every service not needing a display
+    MultiUser,
+    /// Everything, display managers included
+    Graphical,
+}
+
+impl Target {
+    const ALL: [Target; 3] =
+        [Target::Rescue, Target::MultiUser, Target::Graphical];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Rescue => "rescue",
+            Target::MultiUser => "multi-user",
+            Target::Graphical => "graphical",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|target| target.name().as_bytes() == name)
+    }
+
+    /// Whether services part of `other` run in this target
+    pub fn includes(self, other: Target) -> bool {
+        other <= self
+    }
+}
@@ -1,13 +1,37 @@
-use core::{ffi::CStr, ptr};
+use core::ffi::CStr;
 
 use cstr::cstr;
 use libc_print::libc_eprintln;
 
-use crate::{exec::fork_and_execute_command, utils::NixPathExt};
+use crate::{
+    exec::command::Command,
+    fixed::{CStrList, FixedCStr},
+    parser::Words,
+    service::{Service, ServiceName},
+    supervisor::{RestartPolicy, Supervisor},
+    utils::{read_file, NixPathExt},
+    Error,
+};
 
 static GETTY: &CStr = cstr!("/usr/bin/getty");
 static AGETTY: &CStr = cstr!("/usr/bin/agetty");
 
+/// The consoles the kernel writes to, separated by spaces, the last
+/// one being `/dev/console`
+static ACTIVE_CONSOLES: &CStr =
+    cstr!("/sys/class/tty/console/active");
+
+/// The most TTYs that can be configured
+pub const MAX_TTYS: usize = 12;
+
+/// The TTYs gettys run on when none are configured
+const DEFAULT_TTYS: [&[u8]; 8] = [
+    b"tty1", b"tty2", b"tty3", b"tty4", b"tty5", b"tty6", b"tty7",
+    b"tty8",
+];
+
+static BAUD_RATES: &[u8] = b"115200,38400,9600";
+
 fn tty_opener_binary() -> Option<&'static CStr> {
     match (GETTY.is_executable(), AGETTY.is_executable()) {
         (true, _) => Some(GETTY),
@@ -16,44 +40,342 @@ fn tty_opener_binary() -> Option<&'static CStr> {
     }
 }
 
-pub fn open_ttys() {
-    static BAUD_RATES: &CStr = cstr!("115200,38400,9600");
-    // The value for the TERM env variable
-    static TERM: &CStr = cstr!("linux");
-    static TTYS: &[&CStr] = &[
-        cstr!("tty1"),
-        cstr!("tty2"),
-        cstr!("tty3"),
-        cstr!("tty4"),
-        cstr!("tty5"),
-        cstr!("tty6"),
-        cstr!("tty7"),
-        cstr!("tty8"),
-    ];
-
-    let Some(tty_opener) = tty_opener_binary() else {
-        libc_eprintln!("No tty opener found!");
-        return;
+/// Whether `device` is a virtual terminal, e.g. `tty1`, as opposed
+/// to a serial line such as `ttyS0` or `hvc0`, or to `tty`, the
+/// controlling terminal of whoever opens it
+fn is_virtual_terminal(device: &[u8]) -> bool {
+    device.strip_prefix(b"tty").is_some_and(|number| {
+        !number.is_empty() && number.iter().all(u8::is_ascii_digit)
+    })
+}
+
+/// A TTY to run a getty on, as configured through `tty` in
+/// `/etc/incipio/incipio.conf`
+#[derive(Debug, Clone, Copy)]
+pub struct Tty {
+    /// The name of the device, without `/dev/`
+    pub device: FixedCStr<32>,
+    pub baud_rates: FixedCStr<32>,
+    /// The value for the TERM env variable
+    pub term: FixedCStr<32>,
+    /// The getty to run, if not the one found in `/usr/bin`
+    pub getty: Option<FixedCStr<64>>,
+    /// Options given to the getty before the usual arguments
+    pub arguments: CStrList<128>,
+}
+
+impl Tty {
+    /// A TTY with the default settings for `device`, which depend
+    /// on whether it's a virtual terminal or a serial line
+    pub fn new(device: &[u8]) -> crate::Result<Self> {
+        let device = device.strip_prefix(b"/dev/").unwrap_or(device);
+        let term: &[u8] = if is_virtual_terminal(device) {
+            b"linux"
+        } else {
+            b"vt102"
+        };
+
+        Ok(Self {
+            device: FixedCStr::from_bytes(device)?,
+            baud_rates: FixedCStr::from_bytes(BAUD_RATES)?,
+            term: FixedCStr::from_bytes(term)?,
+            getty: None,
+            arguments: CStrList::new(),
+        })
+    }
+
+    /// Parses the value of a `tty` line, which is the device
+    /// followed by optional settings and then, after `--`, options
+    /// for the getty, e.g.
+    ///
+    /// ```text
+    /// ttyS0 baud=115200 term=vt220 getty=/sbin/agetty -- --autologin root
+    /// ```
+    pub fn parse(value: &[u8]) -> crate::Result<Self> {
+        let mut words = Words::new(value);
+        let device = words.next().ok_or(Error::InvalidConfigValue)?;
+        let mut tty = Self::new(device)?;
+
+        for word in words.by_ref() {
+            if word == b"--" {
+                break;
+            }
+
+            let (key, value) = word
+                .iter()
+                .position(|&byte| byte == b'=')
+                .map(|idx| (&word[..idx], &word[idx + 1..]))
+                .ok_or(Error::InvalidConfigValue)?;
+
+            match key {
+                b"baud" => {
+                    tty.baud_rates = FixedCStr::from_bytes(value)?
+                }
+                b"term" => tty.term = FixedCStr::from_bytes(value)?,
+                b"getty" => {
+                    tty.getty = Some(FixedCStr::from_bytes(value)?)
+                }
+                _ => return Err(Error::InvalidConfigValue),
+            }
+        }
+
+        for argument in words {
+            tty.arguments.push(argument)?;
+        }
+
+        Ok(tty)
+    }
+
+    /// Builds the service that opens a getty on this TTY, which is
+    /// named after it, e.g. `getty-tty1`
+    fn getty_service(
+        &self,
+        tty_opener: &CStr,
+    ) -> crate::Result<Service> {
+        let mut name = ServiceName::from_bytes(b"getty-")?;
+        name.push(self.device.as_bytes())?;
+
+        let mut command = Command::new(tty_opener.to_bytes())?;
+        for argument in self.arguments.iter() {
+            command.arg(argument.to_bytes())?;
+        }
+        for argument in [&self.baud_rates, &self.device, &self.term] {
+            command.arg(argument.as_bytes())?;
+        }
+
+        // Gettys open their terminal themselves, but this way they
+        // start off attached to it rather than to incipio's console
+        let mut tty = FixedCStr::<64>::from_bytes(b"/dev/")?;
+        tty.push(self.device.as_bytes())?;
+        command.tty(tty.as_bytes())?;
+
+        let service =
+            Service::new(name, command, RestartPolicy::Always);
+
+        Ok(service)
+    }
+}
+
+/// A fixed-capacity list of TTYs
+#[derive(Debug, Clone, Copy)]
+pub struct Ttys {
+    ttys: [Option<Tty>; MAX_TTYS],
+}
+
+impl Ttys {
+    pub const fn new() -> Self {
+        const EMPTY: Option<Tty> = None;
+
+        Self {
+            ttys: [EMPTY; MAX_TTYS],
+        }
+    }
+
+    pub fn push(&mut self, tty: Tty) -> crate::Result<()> {
+        let slot = self
+            .ttys
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::CapacityExceeded)?;
+        *slot = Some(tty);
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ttys[0].is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tty> {
+        self.ttys.iter().flatten()
+    }
+
+    fn find(&self, device: &[u8]) -> Option<&Tty> {
+        self.iter().find(|tty| tty.device.as_bytes() == device)
+    }
+
+    /// Adds the TTY called `device`, using its settings in
+    /// `configured` if it has any
+    fn push_device(
+        &mut self,
+        device: &[u8],
+        configured: &Ttys,
+    ) -> crate::Result<()> {
+        let tty = match configured.find(device) {
+            Some(tty) => *tty,
+            None => Tty::new(device)?,
+        };
+
+        self.push(tty)
+    }
+}
+
+impl Default for Ttys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Starts a getty on each TTY, which gets respawned whenever the
+/// user logs out.
+///
+/// These are the `configured` TTYs or, if there are none, `tty1` to
+/// `tty8`, plus the serial lines the kernel uses as consoles. The
+/// comma-separated list of TTYs `only`, e.g. `tty1,ttyS0`, replaces
+/// all of these.
+pub fn open_ttys(
+    supervisor: &mut Supervisor,
+    configured: &Ttys,
+    only: Option<&[u8]>,
+) {
+    let mut ttys = Ttys::new();
+
+    let result = match only {
+        Some(only) => only
+            .split(|&byte| byte == b',')
+            .filter(|device| !device.is_empty())
+            .try_for_each(|device| {
+                ttys.push_device(device, configured)
+            }),
+        None if configured.is_empty() => {
+            DEFAULT_TTYS.iter().try_for_each(|device| {
+                ttys.push_device(device, configured)
+            })
+        }
+        None => {
+            ttys = *configured;
+            Ok(())
+        }
     };
 
-    for tty in TTYS {
-        let command = [
-            tty_opener.as_ptr(),
-            BAUD_RATES.as_ptr(),
-            tty.as_ptr(),
-            TERM.as_ptr(),
-            ptr::null(),
-        ];
-        let should_wait = false;
-
-        if let Err(err) =
-            fork_and_execute_command(command, should_wait)
-        {
+    if let Err(err) = result {
+        libc_eprintln!("Ignoring some TTYs: {}", err.description());
+    }
+
+    if only.is_none() {
+        add_serial_consoles(&mut ttys, configured);
+    }
+
+    for tty in ttys.iter() {
+        let tty_opener = match &tty.getty {
+            Some(getty) => Some(getty.as_cstr()),
+            None => tty_opener_binary(),
+        };
+        let Some(tty_opener) = tty_opener else {
+            libc_eprintln!(
+                "No tty opener found for {:?}!",
+                tty.device
+            );
+            continue;
+        };
+
+        let result = tty
+            .getty_service(tty_opener)
+            .and_then(|service| supervisor.supervise(service));
+
+        if let Err(err) = result {
             libc_eprintln!(
                 "Failed to open {:?}: {}",
-                tty,
+                tty.device,
+                err.description()
+            );
+        }
+    }
+}
+
+/// Adds the serial lines among [`ACTIVE_CONSOLES`] to `ttys`, so
+/// that headless machines can be logged into through the console
+/// they boot on.
+fn add_serial_consoles(ttys: &mut Ttys, configured: &Ttys) {
+    let mut consoles = [0; 256];
+    let len = match read_file(ACTIVE_CONSOLES, &mut consoles) {
+        Ok(len) => len,
+        // e.g. no sysfs
+        Err(_) => return,
+    };
+
+    for device in consoles[..len].split(u8::is_ascii_whitespace) {
+        if device.is_empty()
+            || is_virtual_terminal(device)
+            || ttys.find(device).is_some()
+        {
+            continue;
+        }
+
+        if let Err(err) = ttys.push_device(device, configured) {
+            libc_eprintln!(
+                "Failed to add console {:?}: {}",
+                core::str::from_utf8(device).unwrap_or(""),
                 err.description()
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_depend_on_the_device() {
+        let console = Tty::parse(b"/dev/tty1").unwrap();
+        assert_eq!(console.device.as_bytes(), b"tty1");
+        assert_eq!(console.term.as_bytes(), b"linux");
+        assert_eq!(console.baud_rates.as_bytes(), BAUD_RATES);
+
+        let serial = Tty::parse(b"ttyS0").unwrap();
+        assert_eq!(serial.term.as_bytes(), b"vt102");
+    }
+
+    #[test]
+    fn parses_settings_and_getty_options() {
+        let tty = Tty::parse(
+            b"ttyS0 baud=9600 term=vt220 getty=/sbin/agetty \
+              -- --autologin root",
+        )
+        .unwrap();
+
+        assert_eq!(tty.baud_rates.as_bytes(), b"9600");
+        assert_eq!(tty.term.as_bytes(), b"vt220");
+        assert_eq!(
+            tty.getty.as_ref().map(FixedCStr::as_bytes),
+            Some(&b"/sbin/agetty"[..])
+        );
+
+        let mut arguments = tty.arguments.iter();
+        assert_eq!(
+            arguments.next().map(CStr::to_bytes),
+            Some(&b"--autologin"[..])
+        );
+        assert_eq!(
+            arguments.next().map(CStr::to_bytes),
+            Some(&b"root"[..])
+        );
+        assert_eq!(arguments.next(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(matches!(
+            Tty::parse(b"tty1 speed=9600"),
+            Err(Error::InvalidConfigValue)
+        ));
+        assert!(matches!(
+            Tty::parse(b"tty1 autologin"),
+            Err(Error::InvalidConfigValue)
+        ));
+        assert!(matches!(
+            Tty::parse(b""),
+            Err(Error::InvalidConfigValue)
+        ));
+    }
+
+    #[test]
+    fn recognises_virtual_terminals() {
+        assert!(is_virtual_terminal(b"tty1"));
+        assert!(is_virtual_terminal(b"tty12"));
+        assert!(!is_virtual_terminal(b"tty"));
+        assert!(!is_virtual_terminal(b"ttyS0"));
+        assert!(!is_virtual_terminal(b"hvc0"));
+    }
+}
@@ -1,18 +1,22 @@
 use core::{
-    ffi::{c_int, c_void},
+    ffi::{c_char, c_int, c_void, CStr},
     num::NonZeroUsize,
     ops::Not,
     ptr::NonNull,
+    time::Duration,
 };
 
 use libc_print::libc_eprintln;
 use nix::{
+    errno::Errno,
     fcntl::{open, OFlag},
+    libc::{nanosleep, timespec},
     sys::{
         mman::{mmap, munmap, MapFlags, ProtFlags},
         stat::{fstat, Mode},
     },
-    unistd::{access, close, AccessFlags},
+    time::{clock_gettime, ClockId},
+    unistd::{access, close, read, AccessFlags},
     NixPath,
 };
 
@@ -121,5 +125,89 @@ pub trait NixPathExt: NixPath {
     fn is_executable(&self) -> bool {
         access(self, AccessFlags::X_OK).is_ok()
     }
+
+    fn exists(&self) -> bool {
+        access(self, AccessFlags::F_OK).is_ok()
+    }
 }
 impl<P: NixPath + ?Sized> NixPathExt for P {}
+
+/// The time elapsed since some unspecified point in the past, which
+/// is not affected by changes to the system clock.
+pub fn monotonic_time() -> Duration {
+    // CLOCK_MONOTONIC is supported by every Linux version we could
+    // possibly run on, so this can't fail
+    clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(Duration::from)
+        .unwrap_or(Duration::ZERO)
+}
+
+/// Suspends execution for `duration`, going back to sleep if
+/// interrupted.
+pub fn sleep_for(duration: Duration) {
+    let mut remaining = timespec {
+        tv_sec: duration.as_secs() as _,
+        tv_nsec: duration.subsec_nanos() as _,
+    };
+
+    loop {
+        let request = remaining;
+        let ret_val = unsafe { nanosleep(&request, &mut remaining) };
+        if Errno::result(ret_val) != Err(Errno::EINTR) {
+            break;
+        }
+    }
+}
+
+/// Reads from `fd` into `buffer` until it's full or the file ends,
+/// returning how many bytes were read.
+///
+/// Unlike [`FileMapping`], this works for files whose size is not
+/// known in advance, such as the ones in `/proc`.
+pub fn read_up_to(
+    fd: c_int,
+    buffer: &mut [u8],
+) -> nix::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match read(fd, &mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(Errno::EINTR) => continue,
+            Err(errno) => return Err(errno),
+        }
+    }
+
+    Ok(len)
+}
+
+/// Reads the file at `path` into `buffer`, returning how many bytes
+/// were read. Files larger than `buffer` are cut short.
+pub fn read_file<P: ?Sized + NixPath>(
+    path: &P,
+    buffer: &mut [u8],
+) -> nix::Result<usize> {
+    let fd = open(
+        path,
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let result = read_up_to(fd, buffer);
+    let _ = close(fd);
+
+    result
+}
+
+/// Iterates over the command-line arguments of the program,
+/// including `argv[0]`.
+///
+/// # Safety
+///
+/// `argv` must point to `argc` valid C strings, as handed to `main`
+/// by the C runtime.
+pub unsafe fn program_arguments(
+    argc: isize,
+    argv: *const *const c_char,
+) -> impl Iterator<Item = &'static CStr> {
+    (0..argc.max(0)).map(move |idx| CStr::from_ptr(*argv.offset(idx)))
+}
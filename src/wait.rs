@@ -1,4 +1,3 @@
-use libc_print::libc_eprintln;
 use nix::{
     errno::Errno,
     sys::wait::{waitpid, WaitPidFlag, WaitStatus},
@@ -35,20 +34,31 @@ fn log_wait_status(status: WaitStatus) {
         WaitStatus::Exited(_pid, 0) => {
             // Process exited normally
         }
-        WaitStatus::Exited(_pid, exit_code) => {
-            libc_eprintln!("PID exited with code {}", exit_code);
+        WaitStatus::Exited(pid, exit_code) => {
+            crate::debug!(
+                "PID {} exited with code {}",
+                pid,
+                exit_code
+            );
         }
-        WaitStatus::Signaled(_, signal, _) => {
-            libc_eprintln!("PID exited signaled with {}", signal);
+        WaitStatus::Signaled(pid, signal, _) => {
+            crate::debug!(
+                "PID {} exited signaled with {}",
+                pid,
+                signal
+            );
         }
         // Other statuses are not relevant
         _ => {}
     }
 }
 
-/// Reap processes spawned by `incipio`. The value returned is
-/// the amount of processes reaped.
-pub fn reap_child_processes() -> i32 {
+/// Reap processes spawned by `incipio`, calling `on_exit` with the
+/// status of each of them. The value returned is the amount of
+/// processes reaped.
+pub fn reap_child_processes(
+    mut on_exit: impl FnMut(WaitStatus),
+) -> i32 {
     // Equivalent to `(pid_t)-1`; status is then requested for
     // any child process
     let pid = None;
@@ -58,8 +68,17 @@ pub fn reap_child_processes() -> i32 {
     let flags = WaitPidFlag::WNOHANG;
     let mut reaped = 0;
 
-    while wait_pid_no_interrupt(pid, flags).is_ok() {
-        reaped += 1;
+    loop {
+        match wait_pid_no_interrupt(pid, flags) {
+            // There are children left but none of them has exited
+            Ok(WaitStatus::StillAlive) => break,
+            Ok(status) => {
+                on_exit(status);
+                reaped += 1;
+            }
+            // ECHILD: there are no children left
+            Err(_) => break,
+        }
     }
 
     reaped